@@ -1,17 +1,165 @@
-use clap::{ArgAction, Parser};
+use chrono::format::{Item, StrftimeItems};
+use clap::{ArgAction, Parser, ValueEnum};
+use clap_complete::Shell;
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    time::SystemTime,
+};
 
-#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+use crate::colors::Colors;
+use crate::report::report_note;
+
+#[derive(Clone, Default, Debug)]
 pub(crate) enum TimeStyle {
     #[default]
     Default,
     Iso,
+    FullIso,
     Relative,
+    Unix,
+    Precise,
+    /// A strftime pattern, given as `+FORMAT`.
+    Format(String),
+}
+
+impl TimeStyle {
+    /// Parses a style name, or a `+`-prefixed strftime pattern checked up front
+    /// so that a bad one is an argument error rather than a failure mid-listing.
+    fn parse(value: &str) -> Result<TimeStyle, String> {
+        if let Some(format) = value.strip_prefix('+') {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("invalid time format '{format}'"));
+            }
+            return Ok(TimeStyle::Format(format.to_string()));
+        }
+        match value.to_ascii_lowercase().as_str() {
+            "default" => Ok(TimeStyle::Default),
+            "iso" | "long-iso" => Ok(TimeStyle::Iso),
+            "full-iso" => Ok(TimeStyle::FullIso),
+            "relative" => Ok(TimeStyle::Relative),
+            "unix" | "epoch" => Ok(TimeStyle::Unix),
+            "precise" => Ok(TimeStyle::Precise),
+            _ => Err(format!(
+                "expected default, iso, long-iso, full-iso, relative, unix, precise or +FORMAT, got '{value}'"
+            )),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TimeField {
+    Modified,
+    Accessed,
+    Created,
+    Changed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SizeFormat {
+    Binary,
+    Si,
+    Bytes,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub(crate) enum SortBy {
+    #[default]
+    Name,
+    Size,
+    Extension,
+    Version,
+    None,
+    #[value(alias("time"))]
+    Modified,
+    Created,
+    Accessed,
+    Changed,
+}
+
+impl SortBy {
+    /// The timestamp to sort by, newest first, if any.
+    pub(crate) fn time_field(self) -> Option<TimeField> {
+        match self {
+            SortBy::Name | SortBy::Size | SortBy::Extension | SortBy::Version | SortBy::None => {
+                None
+            }
+            SortBy::Modified => Some(TimeField::Modified),
+            SortBy::Created => Some(TimeField::Created),
+            SortBy::Accessed => Some(TimeField::Accessed),
+            SortBy::Changed => Some(TimeField::Changed),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+pub(crate) enum Perms {
+    #[default]
+    Symbolic,
+    Octal,
+    BothInline,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub(crate) enum RelativePrecision {
+    #[default]
+    Approx,
+    Calendar,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ColorScale {
+    Size,
+    Age,
+    All,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
+pub(crate) enum ColorScaleMode {
+    #[default]
+    Fixed,
+    Gradient,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub(crate) enum ColorWhen {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub(crate) enum ErrorFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub(crate) enum QuotingStyle {
+    #[default]
+    Literal,
+    Shell,
+    ShellEscape,
+    C,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum Preset {
+    Minimal,
+    Detailed,
+    Forensic,
 }
 
 #[derive(Debug, Default, Parser)]
 #[clap(version, about = "A very basic ls clone")]
 pub(crate) struct Args {
-    #[clap(default_value = ".", help = "List of files/directories")]
+    #[clap(
+        default_value = ".",
+        help = "List of files/directories, '-' to read them from stdin, one per line or NUL-separated with -0"
+    )]
     pub(crate) paths: Vec<String>,
 
     #[clap(
@@ -19,10 +167,88 @@ pub(crate) struct Args {
         long("all"),
         action = ArgAction::Count,
         default_value_t = 0,
-        help = "Show hidden and 'dot' files.\nUse this twice to also show '.' and '..' directories"
+        help = "Show hidden and 'dot' files.\nUse this twice to also show '.' and '..' directories.\nMore than twice is the same as twice"
     )]
     pub(crate) all: u8,
 
+    #[clap(
+        short('A'),
+        long("almost-all"),
+        default_value_t = false,
+        help = "Show hidden and 'dot' files, but never '.' and '..', even with -aa"
+    )]
+    pub(crate) almost_all: bool,
+
+    #[clap(
+        long("preset"),
+        ignore_case = true,
+        help = "Turn on a set of options: minimal (-1), detailed (-lgH --git --icons) or forensic (-lgHiBZ@ --all-times --perms=both-inline), without overriding options given"
+    )]
+    pub(crate) preset: Option<Preset>,
+
+    #[clap(
+        long("sort"),
+        default_value = "name",
+        ignore_case = true,
+        help = "Sort by name, extension, version (numbers in names compared as numbers), or by size or a timestamp with the largest or newest first.\nnone keeps the order of the directory and of the command line"
+    )]
+    pub(crate) sort: SortBy,
+
+    #[clap(
+        short('S'),
+        long("sort-size"),
+        default_value_t = false,
+        help = "Sort by size, largest first. Same as --sort=size"
+    )]
+    pub(crate) sort_size: bool,
+
+    #[clap(
+        short('X'),
+        long("sort-extension"),
+        default_value_t = false,
+        help = "Sort by extension, files without one first. Same as --sort=extension"
+    )]
+    pub(crate) sort_extension: bool,
+
+    #[clap(
+        short('v'),
+        long("version-sort"),
+        default_value_t = false,
+        help = "Sort numbers in names by value, e.g. file2 before file10. Same as --sort=version"
+    )]
+    pub(crate) version_sort: bool,
+
+    #[clap(
+        short('t'),
+        long("sort-time"),
+        default_value_t = false,
+        help = "Sort by the timestamp picked with --time, by default the one shown (-m, -u, -U or --changed), newest first"
+    )]
+    pub(crate) sort_time: bool,
+
+    #[clap(
+        long("time"),
+        value_enum,
+        value_name = "FIELD",
+        help = "Timestamp to sort by with -t, whichever one is shown"
+    )]
+    pub(crate) time: Option<TimeField>,
+
+    #[clap(
+        short('r'),
+        long("reverse"),
+        default_value_t = false,
+        help = "Reverse the sort order"
+    )]
+    pub(crate) reverse: bool,
+
+    #[clap(
+        long("group-directories-first"),
+        default_value_t = false,
+        help = "List directories before files. With -r, both stay reversed within their group"
+    )]
+    pub(crate) group_directories_first: bool,
+
     #[clap(
         short('x'),
         long("across"),
@@ -31,6 +257,27 @@ pub(crate) struct Args {
     )]
     pub(crate) across: bool,
 
+    #[clap(
+        long("comma"),
+        default_value_t = false,
+        help = "List names separated by commas, filling the width of the terminal (80 columns when unknown)"
+    )]
+    pub(crate) comma: bool,
+
+    #[clap(
+        long("width"),
+        value_name = "N",
+        help = "Lay out the grid and --comma for N columns rather than the terminal width, 0 for no limit"
+    )]
+    pub(crate) width: Option<usize>,
+
+    #[clap(
+        long("grid-balanced"),
+        default_value_t = false,
+        help = "Even out column heights when the grid is filled downwards"
+    )]
+    pub(crate) grid_balanced: bool,
+
     #[clap(
         short('l'),
         long("long"),
@@ -39,6 +286,43 @@ pub(crate) struct Args {
     )]
     pub(crate) long: bool,
 
+    #[clap(
+        short('d'),
+        long("directory"),
+        default_value_t = false,
+        help = "List directories themselves, not their contents"
+    )]
+    pub(crate) directory: bool,
+
+    #[clap(
+        short('R'),
+        long("recursive"),
+        default_value_t = false,
+        help = "List subdirectories recursively"
+    )]
+    pub(crate) recursive: bool,
+
+    #[clap(
+        long("tree"),
+        default_value_t = false,
+        help = "Recurse into directories as a tree"
+    )]
+    pub(crate) tree: bool,
+
+    #[clap(
+        long("level"),
+        value_name = "DEPTH",
+        help = "Limit the depth of the tree"
+    )]
+    pub(crate) level: Option<usize>,
+
+    #[clap(
+        long("total-size"),
+        default_value_t = false,
+        help = "Show the size of directories as the total size of their contents"
+    )]
+    pub(crate) total_size: bool,
+
     #[clap(
         short('B'),
         long("bytes"),
@@ -47,6 +331,20 @@ pub(crate) struct Args {
     )]
     pub(crate) bytes: bool,
 
+    #[clap(
+        long("si"),
+        default_value_t = false,
+        help = "List file sizes in powers of 1000 (kB, MB, GB) rather than 1024. --bytes wins over this"
+    )]
+    pub(crate) si: bool,
+
+    #[clap(
+        long("raw"),
+        default_value_t = false,
+        help = "Machine-readable sizes and times, same as --bytes --time-style=unix"
+    )]
+    pub(crate) raw: bool,
+
     #[clap(
         short('D'),
         long("only-dirs"),
@@ -67,10 +365,18 @@ pub(crate) struct Args {
         short('g'),
         long("group"),
         default_value_t = false,
+        overrides_with = "no_group",
         help = "List each file's group"
     )]
     pub(crate) group: bool,
 
+    #[clap(
+        long("shared-group"),
+        default_value_t = false,
+        help = "Mark with a + and underline the group of files not owned by the owner's primary group"
+    )]
+    pub(crate) shared_group: bool,
+
     #[clap(
         short('i'),
         long("inode"),
@@ -79,6 +385,21 @@ pub(crate) struct Args {
     )]
     pub(crate) inode: bool,
 
+    #[clap(
+        long("inode-hex"),
+        default_value_t = false,
+        help = "List each file's inode number in hexadecimal"
+    )]
+    pub(crate) inode_hex: bool,
+
+    #[clap(
+        short('s'),
+        long("blocks"),
+        default_value_t = false,
+        help = "List each file's allocated size in 1K blocks"
+    )]
+    pub(crate) blocks: bool,
+
     #[clap(
         short('H'),
         long("links"),
@@ -87,6 +408,120 @@ pub(crate) struct Args {
     )]
     pub(crate) links: bool,
 
+    #[clap(
+        long("newer-than-file"),
+        value_name = "PATH",
+        help = "List only entries modified more recently than the given file"
+    )]
+    pub(crate) newer_than_file: Option<PathBuf>,
+
+    #[clap(
+        long("older-than-file"),
+        value_name = "PATH",
+        help = "List only entries modified before the given file"
+    )]
+    pub(crate) older_than_file: Option<PathBuf>,
+
+    #[clap(skip)]
+    pub(crate) colors: Colors,
+
+    #[clap(skip)]
+    pub(crate) newer_than: Option<SystemTime>,
+
+    #[clap(skip)]
+    pub(crate) older_than: Option<SystemTime>,
+
+    #[clap(
+        long("limit"),
+        value_name = "N",
+        help = "Show only the first N entries of each directory"
+    )]
+    pub(crate) limit: Option<usize>,
+
+    #[clap(
+        short('I'),
+        long("ignore-glob"),
+        value_name = "PATTERN",
+        help = "Leave out entries whose name matches the shell pattern, e.g. '*.o'. Can be given more than once"
+    )]
+    pub(crate) ignore_glob: Vec<String>,
+
+    #[clap(
+        long("gitignore"),
+        default_value_t = false,
+        help = "Leave out entries ignored by git, in a git repository"
+    )]
+    pub(crate) gitignore: bool,
+
+    #[clap(
+        long("top"),
+        value_name = "KEY:N",
+        value_parser = parse_top,
+        help = "Show only the N largest or newest entries in long format, KEY being a --sort key such as size or time"
+    )]
+    pub(crate) top: Option<(SortBy, usize)>,
+
+    #[clap(
+        long("merge"),
+        default_value_t = false,
+        help = "List all arguments and directory contents as one sorted listing"
+    )]
+    pub(crate) merge: bool,
+
+    #[clap(
+        short('L'),
+        long("dereference"),
+        default_value_t = false,
+        overrides_with = "no_dereference",
+        help = "Show information for the file a symlink points to"
+    )]
+    pub(crate) dereference: bool,
+
+    #[clap(
+        short('P'),
+        long("no-dereference"),
+        default_value_t = false,
+        overrides_with = "dereference",
+        help = "Show information for symlinks themselves (default).\nOf -L and -P, the last one given wins"
+    )]
+    pub(crate) no_dereference: bool,
+
+    #[clap(
+        long("dereference-command-line"),
+        default_value_t = false,
+        help = "Show information for the file a symlink given as an argument points to, but not for symlinks in directories"
+    )]
+    pub(crate) dereference_command_line: bool,
+
+    #[clap(
+        long("json"),
+        default_value_t = false,
+        help = "Print entries as a JSON array, directory contents after the files given"
+    )]
+    pub(crate) json: bool,
+
+    #[clap(
+        short('F'),
+        long("classify"),
+        default_value_t = false,
+        help = "Mark directories with /, symlinks with @, executables with *, sockets with = and fifos with |"
+    )]
+    pub(crate) classify: bool,
+
+    #[clap(
+        long("icons"),
+        default_value_t = false,
+        help = "Show an icon before each name, for terminals with a Nerd Font"
+    )]
+    pub(crate) icons: bool,
+
+    #[clap(
+        long("hyperlink"),
+        default_value_t = false,
+        help = "Make names links to their files, in terminals that support it. Off without colors"
+    )]
+    pub(crate) hyperlink: bool,
+
     #[clap(
         short('1'),
         long("oneline"),
@@ -95,6 +530,14 @@ pub(crate) struct Args {
     )]
     pub(crate) oneline: bool,
 
+    #[clap(
+        short('0'),
+        long("zero"),
+        default_value_t = false,
+        help = "Print bare names ending with a NUL byte rather than a newline, e.g. for xargs -0"
+    )]
+    pub(crate) zero: bool,
+
     #[clap(
         long("no-permissions"),
         default_value_t = false,
@@ -102,13 +545,213 @@ pub(crate) struct Args {
     )]
     pub(crate) no_permissions: bool,
 
+    #[clap(
+        long("no-user"),
+        default_value_t = false,
+        help = "Suppress the user field"
+    )]
+    pub(crate) no_user: bool,
+
+    #[clap(
+        long("no-group"),
+        default_value_t = false,
+        overrides_with = "group",
+        help = "Suppress the group field, even with -g or --shared-group"
+    )]
+    pub(crate) no_group: bool,
+
+    #[clap(
+        long("show-control-chars"),
+        default_value_t = false,
+        overrides_with = "hide_control_chars",
+        help = "Print non-printable characters in names as is (default when not a terminal)"
+    )]
+    pub(crate) show_control_chars: bool,
+
+    #[clap(
+        short('q'),
+        long("hide-control-chars"),
+        default_value_t = false,
+        overrides_with = "show_control_chars",
+        help = "Print '?' for non-printable characters in names (default on a terminal)"
+    )]
+    pub(crate) hide_control_chars: bool,
+
+    #[clap(
+        long("quoting-style"),
+        value_enum,
+        default_value_t = QuotingStyle::Literal,
+        value_name = "STYLE",
+        help = "Quote names as is (literal), in single quotes for a shell when needed (shell), with $'\\n' escapes for control characters too (shell-escape), or in double quotes with C escapes (c)"
+    )]
+    pub(crate) quoting_style: QuotingStyle,
+
+    #[clap(
+        long("relative-paths"),
+        default_value_t = false,
+        help = "Show each entry's path from the listing root instead of its base name"
+    )]
+    pub(crate) relative_paths: bool,
+
+    #[clap(
+        long("no-quote"),
+        default_value_t = false,
+        help = "Print symlink targets as is, even when hiding control characters"
+    )]
+    pub(crate) no_quote: bool,
+
+    #[clap(
+        long("perms"),
+        ignore_case = true,
+        help = "Permissions format, symbolic unless set by --preset"
+    )]
+    pub(crate) perms: Option<Perms>,
+
+    #[clap(
+        short('n'),
+        long("numeric"),
+        default_value_t = false,
+        help = "Show user and group ids instead of names"
+    )]
+    pub(crate) numeric: bool,
+
+    #[clap(
+        long("allocated"),
+        default_value_t = false,
+        help = "In long format, show the space allocated on disk next to the size, e.g. to spot sparse files"
+    )]
+    pub(crate) allocated: bool,
+
+    #[clap(
+        long("octal"),
+        default_value_t = false,
+        help = "In long format, show permissions in octal in a column of their own"
+    )]
+    pub(crate) octal: bool,
+
+    #[clap(
+        short('m'),
+        long("modified"),
+        default_value_t = false,
+        overrides_with_all = ["accessed", "created", "changed"],
+        help = "Show the modification time (default)"
+    )]
+    pub(crate) modified: bool,
+
+    #[clap(
+        short('u'),
+        long("accessed"),
+        default_value_t = false,
+        overrides_with_all = ["modified", "created", "changed"],
+        help = "Show the access time"
+    )]
+    pub(crate) accessed: bool,
+
+    #[clap(
+        short('U'),
+        long("created"),
+        default_value_t = false,
+        overrides_with_all = ["modified", "accessed", "changed"],
+        help = "Show the creation time, '-' where the file system doesn't record it"
+    )]
+    pub(crate) created: bool,
+
+    #[clap(
+        long("changed"),
+        default_value_t = false,
+        overrides_with_all = ["modified", "accessed", "created"],
+        help = "Show the status change time"
+    )]
+    pub(crate) changed: bool,
+
+    #[clap(
+        long("all-times"),
+        default_value_t = false,
+        help = "In long format, show the modification, access, status change and creation times side by side"
+    )]
+    pub(crate) all_times: bool,
+
     #[clap(
         long("time-style"),
-        default_value = "default",
+        value_parser = TimeStyle::parse,
+        help = "Time format: default, iso (or long-iso), full-iso, relative, unix, precise, or +FORMAT with strftime codes [default: $LSR_TIME_STYLE, $TIME_STYLE or default]"
+    )]
+    pub(crate) time_style: Option<TimeStyle>,
+
+    #[clap(
+        long("utc"),
+        default_value_t = false,
+        help = "Show times in UTC rather than in the local time zone"
+    )]
+    pub(crate) utc: bool,
+
+    #[clap(
+        long("relative-precision"),
+        default_value = "approx",
         ignore_case = true,
-        help = "Time format"
+        help = "Count months and years in relative times as 30/365 days or by the calendar"
+    )]
+    pub(crate) relative_precision: RelativePrecision,
+
+    #[clap(
+        long("dim-metadata"),
+        default_value_t = false,
+        help = "Dim everything but the file names in long listings"
+    )]
+    pub(crate) dim_metadata: bool,
+
+    #[clap(
+        long("color"),
+        value_name = "WHEN",
+        default_value = "auto",
+        ignore_case = true,
+        help = "Use colors always, never, or auto: on a terminal, unless $NO_COLOR is set or $CLICOLOR is 0, and anywhere with $CLICOLOR_FORCE"
+    )]
+    pub(crate) color: ColorWhen,
+
+    #[clap(
+        long("color-scale"),
+        ignore_case = true,
+        help = "Color file sizes and/or ages on a scale"
+    )]
+    pub(crate) color_scale: Option<ColorScale>,
+
+    #[clap(
+        long("color-scale-mode"),
+        default_value = "fixed",
+        ignore_case = true,
+        help = "Use fixed color buckets or a truecolor gradient for --color-scale"
+    )]
+    pub(crate) color_scale_mode: ColorScaleMode,
+
+    #[clap(
+        long("legend"),
+        default_value_t = false,
+        help = "Print what the --color-scale colors mean after the listing"
+    )]
+    pub(crate) legend: bool,
+
+    #[clap(
+        long("git-blame"),
+        default_value_t = false,
+        help = "List the last commit and author of each file in a git repository"
+    )]
+    pub(crate) git_blame: bool,
+
+    #[clap(
+        long("git"),
+        default_value_t = false,
+        help = "In long format, show the staged and unstaged git status of each entry"
+    )]
+    pub(crate) git: bool,
+
+    #[clap(
+        short('Z'),
+        long("context"),
+        default_value_t = false,
+        help = "List each file's security context (SELinux or Smack label)"
     )]
-    pub(crate) time_style: TimeStyle,
+    pub(crate) context: bool,
 
     #[clap(
         short('@'),
@@ -117,4 +760,242 @@ pub(crate) struct Args {
         help = "list each file's extended attributes"
     )]
     pub(crate) extended: bool,
+
+    #[clap(
+        long("header"),
+        default_value_t = false,
+        help = "In long format, label the columns on a first row"
+    )]
+    pub(crate) header: bool,
+
+    #[clap(
+        long("truncate-names"),
+        default_value_t = false,
+        help = "In long format, shorten names with an ellipsis so that each row fits the terminal width"
+    )]
+    pub(crate) truncate_names: bool,
+
+    #[clap(
+        long("max-width"),
+        value_name = "N",
+        help = "In long format, drop columns and then shorten names to fit in N columns.\nColumns go in this order: git blame, context, octal, inode, blocks, allocated, extra times, links, group, git status"
+    )]
+    pub(crate) max_width: Option<usize>,
+
+    #[clap(
+        long("summary"),
+        default_value_t = false,
+        conflicts_with = "json",
+        help = "End with the number of files and directories listed and the total size of the files"
+    )]
+    pub(crate) summary: bool,
+
+    #[clap(
+        long("stat-only"),
+        default_value_t = false,
+        help = "Read and stat entries as usual, but only print statistics to stderr"
+    )]
+    pub(crate) stat_only: bool,
+
+    #[clap(
+        long("error-format"),
+        default_value = "human",
+        ignore_case = true,
+        help = "Report errors on stderr as text or as one JSON record (path, errno, message) per line"
+    )]
+    pub(crate) error_format: ErrorFormat,
+
+    #[clap(
+        long("generate-completions"),
+        value_name = "SHELL",
+        hide = true,
+        help = "Print a completion script for the given shell"
+    )]
+    pub(crate) generate_completions: Option<Shell>,
+}
+
+impl Args {
+    /// Normalizes options and resolves the ones that depend on the file system.
+    pub(crate) fn resolve(&mut self) -> io::Result<()> {
+        if self.all > 2 {
+            let message = format!(
+                "-a given {} times, only two levels are supported.",
+                self.all
+            );
+            report_note(&message, self.error_format);
+            self.all = 2;
+        }
+        // -A is a single -a, whatever the count
+        if self.almost_all {
+            self.all = 1;
+        }
+        self.colors = Colors::from_env();
+        if self.time_style.is_none() {
+            self.time_style = Some(time_style_from_env(self.error_format));
+        }
+        // --comma is a layout of its own, names only
+        if self.comma {
+            self.long = false;
+            self.oneline = false;
+        }
+        // --zero is the one-per-line output, names only and as they are
+        if self.zero {
+            self.oneline = true;
+            self.long = false;
+            self.tree = false;
+            self.hide_control_chars = false;
+        }
+        if !self.show_control_chars && !self.hide_control_chars {
+            self.hide_control_chars = io::stdout().is_terminal();
+        }
+        // presets only fill in what wasn't given explicitly
+        match self.preset {
+            Some(Preset::Minimal) => self.oneline = !self.long,
+            Some(Preset::Detailed) => {
+                self.long = true;
+                self.group = true;
+                self.links = true;
+                self.git = true;
+                self.icons = true;
+            }
+            Some(Preset::Forensic) => {
+                self.long = true;
+                self.group = true;
+                self.links = true;
+                self.inode = true;
+                self.bytes = !self.si;
+                self.context = true;
+                self.extended = true;
+                self.all_times = true;
+                self.perms.get_or_insert(Perms::BothInline);
+            }
+            None => {}
+        }
+        if self.raw {
+            self.bytes = true;
+            self.time_style = Some(TimeStyle::Unix);
+        }
+        if self.sort_size {
+            self.sort = SortBy::Size;
+        }
+        if self.sort_extension {
+            self.sort = SortBy::Extension;
+        }
+        if self.version_sort {
+            self.sort = SortBy::Version;
+        }
+        if self.sort_time {
+            self.sort = match self.time.unwrap_or_else(|| self.time_field()) {
+                TimeField::Modified => SortBy::Modified,
+                TimeField::Accessed => SortBy::Accessed,
+                TimeField::Created => SortBy::Created,
+                TimeField::Changed => SortBy::Changed,
+            };
+        }
+        if let Some((sort, n)) = self.top {
+            self.sort = sort;
+            self.limit = Some(n);
+            self.long = true;
+        }
+        if let Some(path) = &self.newer_than_file {
+            self.newer_than = Some(path.metadata()?.modified()?);
+        }
+        if let Some(path) = &self.older_than_file {
+            self.older_than = Some(path.metadata()?.modified()?);
+        }
+        Ok(())
+    }
+
+    /// The timestamp shown in long format, the last of -m, -u, -U and --changed.
+    pub(crate) fn time_field(&self) -> TimeField {
+        if self.accessed {
+            TimeField::Accessed
+        } else if self.created {
+            TimeField::Created
+        } else if self.changed {
+            TimeField::Changed
+        } else {
+            TimeField::Modified
+        }
+    }
+
+    pub(crate) fn size_format(&self) -> SizeFormat {
+        if self.bytes {
+            SizeFormat::Bytes
+        } else if self.si {
+            SizeFormat::Si
+        } else {
+            SizeFormat::Binary
+        }
+    }
+
+    pub(crate) fn size_scale(&self) -> Option<ColorScaleMode> {
+        match self.color_scale {
+            Some(ColorScale::Size | ColorScale::All) => Some(self.color_scale_mode),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn age_scale(&self) -> Option<ColorScaleMode> {
+        match self.color_scale {
+            Some(ColorScale::Age | ColorScale::All) => Some(self.color_scale_mode),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the default time style from `LSR_TIME_STYLE`, or GNU's `TIME_STYLE`.
+fn time_style_from_env(error_format: ErrorFormat) -> TimeStyle {
+    for var in ["LSR_TIME_STYLE", "TIME_STYLE"] {
+        if let Ok(value) = env::var(var) {
+            // GNU ls only applies `posix-` styles outside the POSIX locale
+            let value = value.strip_prefix("posix-").unwrap_or(&value);
+            match TimeStyle::parse(value) {
+                Ok(time_style) => return time_style,
+                Err(_) => report_note(&format!("Ignoring invalid {var}: {value}"), error_format),
+            }
+        }
+    }
+    TimeStyle::default()
+}
+
+/// Parses the `KEY:N` spec of `--top`, e.g. `size:10`.
+fn parse_top(spec: &str) -> Result<(SortBy, usize), String> {
+    let (key, n) = spec
+        .split_once(':')
+        .ok_or_else(|| format!("expected KEY:N, got '{spec}'"))?;
+    let sort = SortBy::from_str(key, true)?;
+    let n = n.parse().map_err(|_| format!("invalid count '{n}'"))?;
+    Ok((sort, n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_style_names_and_formats() {
+        assert!(matches!(TimeStyle::parse("long-iso"), Ok(TimeStyle::Iso)));
+        assert!(matches!(
+            TimeStyle::parse("FULL-ISO"),
+            Ok(TimeStyle::FullIso)
+        ));
+        assert!(matches!(
+            TimeStyle::parse("+%Y-%m-%d %H:%M"),
+            Ok(TimeStyle::Format(f)) if f == "%Y-%m-%d %H:%M"
+        ));
+        assert!(TimeStyle::parse("+%Q").is_err());
+        assert!(TimeStyle::parse("+%").is_err());
+        assert!(TimeStyle::parse("sometime").is_err());
+    }
+
+    #[test]
+    fn parse_top_specs() {
+        assert_eq!(parse_top("size:10"), Ok((SortBy::Size, 10)));
+        assert_eq!(parse_top("TIME:3"), Ok((SortBy::Modified, 3)));
+        assert!(parse_top("size").is_err());
+        assert!(parse_top("bogus:3").is_err());
+        assert!(parse_top("size:-1").is_err());
+        assert!(parse_top("size:").is_err());
+    }
 }
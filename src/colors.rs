@@ -0,0 +1,83 @@
+use std::{collections::HashMap, env};
+
+/// File name colors from `LS_COLORS`, as written by `dircolors`: codes by file
+/// type, e.g. `di=01;34`, and by name suffix, e.g. `*.tar=01;31`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Colors {
+    types: HashMap<String, String>,
+    suffixes: Vec<(String, String)>,
+}
+
+impl Colors {
+    /// Reads `LS_COLORS`, skipping the entries it can't make sense of.
+    pub(crate) fn from_env() -> Colors {
+        let mut colors = Colors::default();
+        let Ok(value) = env::var("LS_COLORS") else {
+            return colors;
+        };
+        for entry in value.split(':') {
+            let Some((key, code)) = entry.split_once('=') else {
+                continue;
+            };
+            if code.is_empty() || !code.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                continue;
+            }
+            match key.strip_prefix('*') {
+                Some(suffix) => colors
+                    .suffixes
+                    .push((suffix.to_ascii_lowercase(), code.to_string())),
+                None => {
+                    colors.types.insert(key.to_string(), code.to_string());
+                }
+            }
+        }
+        colors
+    }
+
+    /// The code of the first of `keys` that is set, `*` standing for the
+    /// suffix of `name`. The longest suffix wins, ignoring case.
+    pub(crate) fn lookup(&self, keys: &[&str], name: &str) -> Option<&str> {
+        keys.iter().find_map(|&key| {
+            if key != "*" {
+                return self.types.get(key).map(String::as_str);
+            }
+            let name = name.to_ascii_lowercase();
+            self.suffixes
+                .iter()
+                .filter(|(suffix, _)| name.ends_with(suffix.as_str()))
+                .max_by_key(|(suffix, _)| suffix.len())
+                .map(|(_, code)| code.as_str())
+        })
+    }
+}
+
+/// `text` in the SGR `code`, unless colors are off.
+pub(crate) fn paint(code: &str, text: &str) -> String {
+    if colored::control::SHOULD_COLORIZE.should_colorize() {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_env_and_lookup() {
+        // the only test that touches LS_COLORS
+        env::set_var("LS_COLORS", "di=01;31:ln=bad:*.tar=01;32:*.TAR.GZ=35:or=");
+        let colors = Colors::from_env();
+        env::remove_var("LS_COLORS");
+
+        assert_eq!(colors.lookup(&["di"], "src"), Some("01;31"));
+        // invalid and empty codes are skipped
+        assert_eq!(colors.lookup(&["ln"], "link"), None);
+        assert_eq!(colors.lookup(&["or", "di"], "x"), Some("01;31"));
+        // the longest suffix wins, ignoring case
+        assert_eq!(colors.lookup(&["*"], "a.tar"), Some("01;32"));
+        assert_eq!(colors.lookup(&["*"], "a.tar.gz"), Some("35"));
+        assert_eq!(colors.lookup(&["*"], "a.zip"), None);
+    }
+}
@@ -1,15 +1,30 @@
 use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     fs::{self, FileType, Metadata},
     io,
-    os::unix::fs::{FileTypeExt, MetadataExt},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::{Duration, SystemTime},
 };
 
-use chrono::{DateTime, Local};
-use colored::{ColoredString, Colorize};
-use uzers::{get_group_by_gid, get_user_by_uid};
+use chrono::{DateTime, Datelike, FixedOffset, Local, Utc};
+use colored::{Color, ColoredString, Colorize};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use uzers::{get_group_by_gid, get_user_by_uid, Group, User};
 
-use crate::cli::TimeStyle;
+use crate::cli::{
+    Args, ColorScaleMode, Perms, QuotingStyle, RelativePrecision, SizeFormat, TimeField, TimeStyle,
+};
+use crate::colors::{self, Colors};
+use crate::report::report;
 
 pub(crate) fn file_type(file_type: FileType) -> ColoredString {
     if file_type.is_symlink() {
@@ -23,14 +38,46 @@ pub(crate) fn file_type(file_type: FileType) -> ColoredString {
     } else if file_type.is_char_device() {
         "c".magenta()
     } else if file_type.is_fifo() {
-        "p".blue()
+        "p".yellow()
     } else if file_type.is_socket() {
-        "s".green()
+        "s".red()
     } else {
         "?".red()
     }
 }
 
+/// File type as a word, for `--json`.
+pub(crate) fn type_name(file_type: FileType) -> &'static str {
+    if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_file() {
+        "file"
+    } else if file_type.is_block_device() {
+        "block"
+    } else if file_type.is_char_device() {
+        "char"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else if file_type.is_socket() {
+        "socket"
+    } else {
+        "unknown"
+    }
+}
+
+static VANISHED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts an entry that was deleted after its directory was read.
+pub(crate) fn note_vanished() {
+    VANISHED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn vanished() -> usize {
+    VANISHED.load(Ordering::Relaxed)
+}
+
 pub(crate) fn metadata(path: &Path) -> io::Result<fs::Metadata> {
     if path.is_symlink() {
         path.symlink_metadata()
@@ -39,6 +86,133 @@ pub(crate) fn metadata(path: &Path) -> io::Result<fs::Metadata> {
     }
 }
 
+/// Canonicalizes the directory the entry is in, but not the entry itself, so
+/// a symlink and its target are still told apart.
+pub(crate) fn canonical_entry(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            parent
+                .canonicalize()
+                .map(|p| p.join(name))
+                .unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+/// A file with everything the listing needs from the file system, fetched
+/// once up front so sorting and formatting don't stat it again.
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub(crate) path: PathBuf,
+    pub(crate) md: Metadata,
+    /// Where a symlink points to, as stored in the link.
+    pub(crate) target: Option<PathBuf>,
+    /// Metadata of the file a symlink points to, `None` if the link is broken.
+    pub(crate) target_md: Option<Metadata>,
+    pub(crate) xattrs: Vec<String>,
+    /// Size of everything below a directory, with `--total-size`.
+    pub(crate) total_size: Option<u64>,
+    /// Whether this is the `.` or `..` added to a listing with -aa, rather
+    /// than, say, `..` given as an argument.
+    pub(crate) dot: bool,
+}
+
+impl Entry {
+    pub(crate) fn new(path: PathBuf, args: &Args) -> io::Result<Entry> {
+        let md = metadata(&path)?;
+        let (target, target_md) = if md.is_symlink() {
+            (fs::read_link(&path).ok(), Some(path.metadata()))
+        } else {
+            (None, None)
+        };
+        // a broken link is listed as itself, like `ls -L` does after
+        // reporting it
+        let target_md = match target_md {
+            Some(Err(e)) if args.dereference => {
+                report(&path, &e, args.error_format);
+                None
+            }
+            target_md => target_md.and_then(Result::ok),
+        };
+        let xattrs = if args.long { xattrs(&path) } else { vec![] };
+        let entry = Entry {
+            path,
+            md,
+            target,
+            target_md,
+            xattrs,
+            total_size: None,
+            dot: false,
+        };
+        // a dereferenced link is listed as its target, so it is colored and
+        // classified by the target's type too
+        Ok(if args.dereference {
+            entry.dereferenced()
+        } else {
+            entry
+        })
+    }
+
+    /// The entry a symlink points to, under the name of the link. A broken
+    /// link is kept as is.
+    pub(crate) fn dereferenced(mut self) -> Entry {
+        if let Some(md) = self.target_md.take() {
+            self.md = md;
+            self.target = None;
+        }
+        self
+    }
+
+    /// Whether this is the `.` or `..` entry of a listing.
+    pub(crate) fn is_dot(&self) -> bool {
+        self.dot
+    }
+
+    /// The name the entry is listed under: `.` and `..` of a listing, else
+    /// the last component, or the whole path when it has none, e.g. `/` or
+    /// `../..`.
+    pub(crate) fn name(&self) -> String {
+        if self.is_dot() {
+            return self.dots().to_string();
+        }
+        match self.path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => self.path.to_string_lossy().to_string(),
+        }
+    }
+
+    /// `.` or `..` for a dot entry, which `Path` can't tell since it drops
+    /// trailing `.` components.
+    fn dots(&self) -> &'static str {
+        if self.path.as_os_str().as_bytes().ends_with(b"..") {
+            ".."
+        } else {
+            "."
+        }
+    }
+
+    /// Length of a file, or the total size of a directory when computed.
+    pub(crate) fn size(&self) -> u64 {
+        self.total_size.unwrap_or(self.md.len())
+    }
+
+    /// Whether this is a directory, or a symlink to one.
+    pub(crate) fn is_dir(&self) -> bool {
+        self.target_md.as_ref().unwrap_or(&self.md).is_dir()
+    }
+
+    /// Whether this is a regular file, or a symlink to one.
+    pub(crate) fn is_file(&self) -> bool {
+        self.target_md.as_ref().unwrap_or(&self.md).is_file()
+    }
+}
+
 #[rustfmt::skip]
 pub(crate) fn format_mode(md: &Metadata, has_xattr: bool) -> String {
     let mode = md.mode();
@@ -46,17 +220,40 @@ pub(crate) fn format_mode(md: &Metadata, has_xattr: bool) -> String {
         file_type(md.file_type()),
         if mode & 0b100000000 != 0 { "r".yellow() } else { "-".white() },
         if mode & 0b010000000 != 0 { "w".red()    } else { "-".white() },
-        if mode & 0b001000000 != 0 { "x".green()  } else { "-".white() },
+        execute(mode, 0b001000000, 0o4000, 's'),
         if mode & 0b000100000 != 0 { "r".yellow() } else { "-".white() },
         if mode & 0b000010000 != 0 { "w".red()    } else { "-".white() },
-        if mode & 0b000001000 != 0 { "x".green()  } else { "-".white() },
+        execute(mode, 0b000001000, 0o2000, 's'),
         if mode & 0b000000100 != 0 { "r".yellow() } else { "-".white() },
         if mode & 0b000000010 != 0 { "w".red()    } else { "-".white() },
-        if mode & 0b000000001 != 0 { "x".green()  } else { "-".white() },
+        execute(mode, 0b000000001, 0o1000, 't'),
         if has_xattr { "@" } else { "" }
     )
 }
 
+/// An execute position, showing setuid, setgid or sticky as `special`,
+/// uppercase when the execute bit itself is off.
+fn execute(mode: u32, bit: u32, special_bit: u32, special: char) -> ColoredString {
+    match (mode & bit != 0, mode & special_bit != 0) {
+        (true, true) => special.to_string().green(),
+        (false, true) => special.to_ascii_uppercase().to_string().white(),
+        (true, false) => "x".green(),
+        (false, false) => "-".white(),
+    }
+}
+
+pub(crate) fn format_octal(md: &Metadata) -> ColoredString {
+    format!("{:04o}", md.mode() & 0o7777).white()
+}
+
+pub(crate) fn format_perms(md: &Metadata, has_xattr: bool, perms: Perms) -> String {
+    match perms {
+        Perms::Symbolic => format_mode(md, has_xattr),
+        Perms::Octal => format_octal(md).to_string(),
+        Perms::BothInline => format!("{} ({})", format_mode(md, has_xattr), format_octal(md)),
+    }
+}
+
 pub(crate) fn xattrs(path: &Path) -> Vec<String> {
     match xattr::list(path) {
         Ok(attrs) => attrs
@@ -66,66 +263,294 @@ pub(crate) fn xattrs(path: &Path) -> Vec<String> {
     }
 }
 
-pub(crate) fn user_name(uid: u32) -> ColoredString {
-    get_user_by_uid(uid)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Lsm {
+    SELinux,
+    Smack,
+    AppArmor,
+}
+
+/// Detects the active Linux security module that labels files.
+pub(crate) fn active_lsm() -> Option<Lsm> {
+    let lsms = fs::read_to_string("/sys/kernel/security/lsm").unwrap_or_default();
+    let lsms: Vec<&str> = lsms.trim().split(',').collect();
+    if lsms.contains(&"selinux") || Path::new("/sys/fs/selinux/enforce").exists() {
+        Some(Lsm::SELinux)
+    } else if lsms.contains(&"smack") || Path::new("/sys/fs/smackfs").exists() {
+        Some(Lsm::Smack)
+    } else if lsms.contains(&"apparmor") {
+        Some(Lsm::AppArmor)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn security_context(path: &Path, lsm: Option<Lsm>) -> ColoredString {
+    let attr = match lsm {
+        Some(Lsm::SELinux) => "security.selinux",
+        Some(Lsm::Smack) => "security.SMACK64",
+        // AppArmor confines by path, files carry no label
+        Some(Lsm::AppArmor) | None => return "?".white(),
+    };
+    match xattr::get(path, attr) {
+        Ok(Some(label)) => String::from_utf8_lossy(&label)
+            .trim_end_matches('\0')
+            .to_string()
+            .cyan(),
+        _ => "?".white(),
+    }
+}
+
+thread_local! {
+    /// Users and groups by id, so that each id is looked up only once.
+    static USERS: RefCell<HashMap<u32, Option<User>>> = RefCell::new(HashMap::new());
+    static GROUPS: RefCell<HashMap<u32, Option<Group>>> = RefCell::new(HashMap::new());
+}
+
+fn lookup_user(uid: u32) -> Option<User> {
+    USERS.with(|users| {
+        users
+            .borrow_mut()
+            .entry(uid)
+            .or_insert_with(|| get_user_by_uid(uid))
+            .clone()
+    })
+}
+
+fn lookup_group(gid: u32) -> Option<Group> {
+    GROUPS.with(|groups| {
+        groups
+            .borrow_mut()
+            .entry(gid)
+            .or_insert_with(|| get_group_by_gid(gid))
+            .clone()
+    })
+}
+
+pub(crate) fn user_name(uid: u32, numeric: bool) -> ColoredString {
+    if numeric {
+        return uid.to_string().bright_yellow();
+    }
+    lookup_user(uid)
         .map(|u| u.name().to_string_lossy().to_string())
         .unwrap_or_else(|| uid.to_string())
         .bright_yellow()
 }
 
-pub(crate) fn group_name(gid: u32) -> ColoredString {
-    get_group_by_gid(gid)
+pub(crate) fn group_name(gid: u32, numeric: bool) -> ColoredString {
+    if numeric {
+        return gid.to_string().yellow();
+    }
+    lookup_group(gid)
         .map(|g| g.name().to_string_lossy().to_string())
         .unwrap_or_else(|| gid.to_string())
         .yellow()
 }
 
-pub(crate) fn modified_date(md: &Metadata, time_style: TimeStyle) -> String {
-    let modified: DateTime<Local> = DateTime::from(md.modified().unwrap());
-    match time_style {
-        TimeStyle::Default => date_default(modified),
-        TimeStyle::Iso => date_iso(modified),
-        TimeStyle::Relative => date_relative(modified),
+pub(crate) fn is_shared_group(md: &Metadata) -> bool {
+    lookup_user(md.uid())
+        .map(|u| u.primary_group_id() != md.gid())
+        .unwrap_or(false)
+}
+
+/// The group, with a `+` after it and underlined with `mark_shared` when it
+/// isn't the owner's primary group, so that it shows without colors too.
+pub(crate) fn group(md: &Metadata, mark_shared: bool, numeric: bool) -> String {
+    let name = group_name(md.gid(), numeric);
+    if mark_shared && is_shared_group(md) {
+        format!("{}{}", name.underline(), "+".yellow())
+    } else {
+        name.to_string()
+    }
+}
+
+/// The given timestamp, or `None` where the file system doesn't record it.
+pub(crate) fn timestamp(md: &Metadata, field: TimeField) -> Option<SystemTime> {
+    match field {
+        TimeField::Modified => md.modified().ok(),
+        TimeField::Accessed => md.accessed().ok(),
+        TimeField::Created => md.created().ok(),
+        TimeField::Changed => {
+            let since_epoch = Duration::new(md.ctime() as u64, md.ctime_nsec() as u32);
+            Some(SystemTime::UNIX_EPOCH + since_epoch)
+        }
+    }
+}
+
+/// A timestamp, usually the one picked with -m, -u, -U or --changed, in the
+/// --time-style format, or a dash when the file system doesn't record it.
+pub(crate) fn file_date(md: &Metadata, field: TimeField, args: &Args) -> String {
+    let Some(time) = timestamp(md, field) else {
+        return "-".dimmed().to_string();
+    };
+    let date_time = if args.utc {
+        DateTime::<Utc>::from(time).fixed_offset()
+    } else {
+        DateTime::<Local>::from(time).fixed_offset()
+    };
+    let color = args
+        .age_scale()
+        .map(|mode| age_color(now(date_time) - date_time, mode));
+    match args.time_style.as_ref().unwrap_or(&TimeStyle::Default) {
+        TimeStyle::Default => date_default(date_time, color),
+        TimeStyle::Iso => date_iso(date_time, color),
+        TimeStyle::FullIso => date_full_iso(date_time, color),
+        TimeStyle::Relative => date_relative(date_time, color, args.relative_precision),
+        TimeStyle::Precise => date_precise(date_time, color),
+        TimeStyle::Format(format) => format!(
+            "{}",
+            date_time
+                .format(format)
+                .to_string()
+                .color(color.unwrap_or(Color::Magenta))
+        ),
+        TimeStyle::Unix => format!(
+            "{}",
+            date_time
+                .timestamp()
+                .to_string()
+                .color(color.unwrap_or(Color::Magenta))
+        ),
     }
 }
 
-pub(crate) fn date_default(date_time: DateTime<Local>) -> String {
-    let now = Local::now();
+/// The current time in the time zone of `date_time`, so that calendar fields
+/// compare as they are shown.
+fn now(date_time: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&date_time.timezone())
+}
+
+pub(crate) fn date_default(date_time: DateTime<FixedOffset>, color: Option<Color>) -> String {
+    let now = now(date_time);
     let duration = now - date_time;
+    let color = color.unwrap_or(Color::Magenta);
 
     if duration.num_days() / 365 > 1 {
-        format!("{}", date_time.format("%e %b  %Y").to_string().magenta())
+        format!("{}", date_time.format("%e %b  %Y").to_string().color(color))
     } else {
-        format!("{}", date_time.format("%e %b %H:%M").to_string().magenta())
+        format!(
+            "{}",
+            date_time.format("%e %b %H:%M").to_string().color(color)
+        )
     }
 }
 
-pub(crate) fn date_iso(date_time: DateTime<Local>) -> String {
+pub(crate) fn date_iso(date_time: DateTime<FixedOffset>, color: Option<Color>) -> String {
+    format!(
+        "{} {}",
+        date_time
+            .format("%Y-%m-%d")
+            .to_string()
+            .color(color.unwrap_or(Color::Magenta)),
+        date_time
+            .format("%H:%M")
+            .to_string()
+            .color(color.unwrap_or(Color::BrightMagenta))
+    )
+}
+
+pub(crate) fn date_full_iso(date_time: DateTime<FixedOffset>, color: Option<Color>) -> String {
     format!(
         "{} {}",
-        date_time.format("%Y-%m-%d").to_string().magenta(),
-        date_time.format("%H:%M").to_string().bright_magenta()
+        date_time
+            .format("%Y-%m-%d")
+            .to_string()
+            .color(color.unwrap_or(Color::Magenta)),
+        date_time
+            .format("%H:%M:%S %z")
+            .to_string()
+            .color(color.unwrap_or(Color::BrightMagenta))
     )
 }
 
-pub(crate) fn date_relative(date_time: DateTime<Local>) -> String {
-    fn pluralize(n: i64, s: &str) -> String {
+pub(crate) fn date_precise(date_time: DateTime<FixedOffset>, color: Option<Color>) -> String {
+    format!(
+        "{} {}",
+        date_time
+            .format("%Y-%m-%d")
+            .to_string()
+            .color(color.unwrap_or(Color::Magenta)),
+        date_time
+            .format("%H:%M:%S.%f")
+            .to_string()
+            .color(color.unwrap_or(Color::BrightMagenta))
+    )
+}
+
+/// Whole calendar months from `from` to `to`, e.g. Jan 31 to Feb 28 is 0.
+fn calendar_months(from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> i64 {
+    let months = (to.year() - from.year()) as i64 * 12 + to.month() as i64 - from.month() as i64;
+    if (to.day(), to.time()) < (from.day(), from.time()) {
+        months - 1
+    } else {
+        months
+    }
+}
+
+/// How long ago `date_time` was, e.g. `3 days ago`, or how far ahead, e.g.
+/// `in 5 minutes`, for clock skew. Padded to the same width whatever the
+/// value, so that relative times line up.
+pub(crate) fn date_relative(
+    date_time: DateTime<FixedOffset>,
+    color: Option<Color>,
+    precision: RelativePrecision,
+) -> String {
+    // as wide as "99 minutes ago"
+    const WIDTH: usize = 14;
+    let now = now(date_time);
+    let future = date_time > now;
+    let (from, to) = if future {
+        (now, date_time)
+    } else {
+        (date_time, now)
+    };
+    let duration = to - from;
+
+    if duration.num_seconds() < 1 {
+        let text = "just now".color(color.unwrap_or(Color::Magenta));
+        return format!("{text}{}", " ".repeat(WIDTH - 8));
+    }
+
+    let pluralize = |n: i64, s: &str| {
         let unit = if n == 1 {
             s.to_string()
         } else {
             format!("{}s", s)
         };
-        format!("{:>2} {}", n.to_string().bright_magenta(), unit.magenta())
-    }
+        let plain = if future {
+            format!("in {n} {unit}")
+        } else {
+            format!("{n:>2} {unit} ago")
+        };
+        let n = n.to_string().color(color.unwrap_or(Color::BrightMagenta));
+        let unit = unit.color(color.unwrap_or(Color::Magenta));
+        let text = if future {
+            format!("{} {n} {unit}", "in".color(color.unwrap_or(Color::Magenta)))
+        } else {
+            format!(
+                "{:>2} {unit} {}",
+                n,
+                "ago".color(color.unwrap_or(Color::Magenta))
+            )
+        };
+        format!("{text}{}", " ".repeat(WIDTH.saturating_sub(plain.len())))
+    };
 
-    let now = Local::now();
-    let duration = now - date_time;
     if duration.num_minutes() < 1 {
         pluralize(duration.num_seconds(), "second")
     } else if duration.num_hours() < 1 {
         pluralize(duration.num_minutes(), "minute")
     } else if duration.num_days() < 1 {
         pluralize(duration.num_hours(), "hour")
+    } else if precision == RelativePrecision::Calendar {
+        let months = calendar_months(from, to);
+        if months < 1 {
+            pluralize(duration.num_days(), "day")
+        } else if months < 12 {
+            pluralize(months, "month")
+        } else {
+            pluralize(months / 12, "year")
+        }
     } else if duration.num_days() < 30 {
         pluralize(duration.num_days(), "day")
     } else if duration.num_days() < 365 {
@@ -135,67 +560,700 @@ pub(crate) fn date_relative(date_time: DateTime<Local>) -> String {
     }
 }
 
-pub(crate) fn file_name(path: &Path, long: bool) -> String {
-    if path == PathBuf::from(".") {
-        return format!("{}/", ".".blue());
-    } else if path == PathBuf::from("..") {
-        return format!("{}/", "..".blue());
+/// Replaces non-printable characters with `?` when `hide_control` is set.
+pub(crate) fn printable(name: &str, hide_control: bool) -> String {
+    if hide_control {
+        name.chars()
+            .map(|c| if c.is_control() { '?' } else { c })
+            .collect()
+    } else {
+        name.to_string()
     }
+}
 
-    let name = path
-        .file_name()
-        .map(|f| f.to_string_lossy().to_string())
-        .unwrap_or_default();
-    let file_type = metadata(path).unwrap().file_type();
+/// Quotes `name` in the given style, as `ls --quoting-style` does. Control
+/// characters left bare by the style are hidden with `hide_control`.
+pub(crate) fn quote(name: &str, style: QuotingStyle, hide_control: bool) -> String {
+    // characters a shell takes as they are
+    let plain = |c: char| c.is_alphanumeric() || "%+,-./:=@_".contains(c);
+    match style {
+        QuotingStyle::Literal => printable(name, hide_control),
+        QuotingStyle::Shell | QuotingStyle::ShellEscape
+            if !name.is_empty() && name.chars().all(plain) =>
+        {
+            name.to_string()
+        }
+        QuotingStyle::Shell => {
+            format!("'{}'", printable(name, hide_control).replace('\'', "'\\''"))
+        }
+        QuotingStyle::ShellEscape => {
+            // runs of control characters go in $'...', the rest in '...'
+            let mut quoted = String::new();
+            let mut chars = name.chars().peekable();
+            while let Some(&c) = chars.peek() {
+                if c.is_control() {
+                    quoted.push_str("$'");
+                    while let Some(c) = chars.next_if(|c| c.is_control()) {
+                        quoted.push_str(&c_escape(c));
+                    }
+                } else {
+                    quoted.push('\'');
+                    while let Some(c) = chars.next_if(|c| !c.is_control()) {
+                        match c {
+                            '\'' => quoted.push_str("'\\''"),
+                            c => quoted.push(c),
+                        }
+                    }
+                }
+                quoted.push('\'');
+            }
+            quoted
+        }
+        QuotingStyle::C => {
+            let escaped: String = name
+                .chars()
+                .map(|c| match c {
+                    '"' => "\\\"".to_string(),
+                    c => c_escape(c),
+                })
+                .collect();
+            format!("\"{escaped}\"")
+        }
+    }
+}
+
+/// `c` as in a C string literal, control characters escaped.
+fn c_escape(c: char) -> String {
+    match c {
+        '\\' => "\\\\".to_string(),
+        '\u{7}' => "\\a".to_string(),
+        '\u{8}' => "\\b".to_string(),
+        '\t' => "\\t".to_string(),
+        '\n' => "\\n".to_string(),
+        '\u{b}' => "\\v".to_string(),
+        '\u{c}' => "\\f".to_string(),
+        '\r' => "\\r".to_string(),
+        c if c.is_control() && (c as u32) < 0o400 => format!("\\{:03o}", c as u32),
+        c if c.is_control() => format!("\\u{:04x}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+/// Shortens `name` to at most `width` columns with an ellipsis in the middle,
+/// keeping the extension when there is room for it.
+pub(crate) fn fit_name(name: &str, width: usize) -> String {
+    if name.width() <= width {
+        return name.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let ext = match name.rfind('.') {
+        Some(i) if i > 0 && name[i..].width() + 2 <= width => &name[i..],
+        _ => "",
+    };
+    let mut budget = width - ext.width() - 1;
+    let mut fitted = String::new();
+    for c in name.chars() {
+        let w = c.width().unwrap_or(0);
+        if w > budget {
+            break;
+        }
+        budget -= w;
+        fitted.push(c);
+    }
+    format!("{fitted}…{ext}")
+}
+
+pub(crate) fn file_name(entry: &Entry, long: bool, args: &Args) -> String {
+    file_name_fitting(entry, long, args, None)
+}
+
+/// Same as `file_name`, with the name and symlink target shortened so that
+/// the whole thing fits in `width` columns.
+pub(crate) fn file_name_fitting(
+    entry: &Entry,
+    long: bool,
+    args: &Args,
+    width: Option<usize>,
+) -> String {
+    // the icon and a space take two columns
+    let width = if args.icons {
+        width.map(|w| w.saturating_sub(2))
+    } else {
+        width
+    };
+    let mut name = styled_name(entry, long, args, width);
+    // links are escape codes too, so they go wherever colors do
+    if args.hyperlink && colored::control::SHOULD_COLORIZE.should_colorize() {
+        name = hyperlink(&entry.path, &name);
+    }
+    if args.icons {
+        name = format!("{} {name}", icon(entry));
+    }
+    name
+}
+
+/// Wraps `text` in an OSC 8 escape linking to `path`, made absolute.
+fn hyperlink(path: &Path, text: &str) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut url = format!("file://{}", hostname());
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            url.push(b as char);
+        } else {
+            url.push_str(&format!("%{b:02X}"));
+        }
+    }
+    format!("\x1b]8;;{url}\x1b\\{text}\x1b]8;;\x1b\\")
+}
+
+fn hostname() -> &'static str {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        fs::read_to_string("/proc/sys/kernel/hostname")
+            .or_else(|_| fs::read_to_string("/etc/hostname"))
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default()
+    })
+}
+
+/// Nerd Font icons of common file extensions.
+const ICONS: [(&str, char); 30] = [
+    ("c", '\u{e61e}'),
+    ("cpp", '\u{e61d}'),
+    ("css", '\u{e749}'),
+    ("gif", '\u{f1c5}'),
+    ("go", '\u{e626}'),
+    ("gz", '\u{f410}'),
+    ("h", '\u{f0fd}'),
+    ("html", '\u{f13b}'),
+    ("java", '\u{e256}'),
+    ("jpeg", '\u{f1c5}'),
+    ("jpg", '\u{f1c5}'),
+    ("js", '\u{e74e}'),
+    ("json", '\u{e60b}'),
+    ("lock", '\u{f023}'),
+    ("md", '\u{f48a}'),
+    ("pdf", '\u{f1c1}'),
+    ("png", '\u{f1c5}'),
+    ("py", '\u{e606}'),
+    ("rb", '\u{e21e}'),
+    ("rs", '\u{e7a8}'),
+    ("sh", '\u{f489}'),
+    ("svg", '\u{f1c5}'),
+    ("tar", '\u{f410}'),
+    ("toml", '\u{e615}'),
+    ("ts", '\u{e628}'),
+    ("txt", '\u{f15c}'),
+    ("xml", '\u{f05c0}'),
+    ("yaml", '\u{e615}'),
+    ("yml", '\u{e615}'),
+    ("zip", '\u{f410}'),
+];
+
+/// Nerd Font icon for --icons, by file type and then by extension.
+pub(crate) fn icon(entry: &Entry) -> char {
+    if entry.md.is_symlink() {
+        return if entry.is_dir() {
+            '\u{f482}'
+        } else {
+            '\u{f481}'
+        };
+    }
+    if entry.md.is_dir() {
+        return '\u{f115}';
+    }
+    let ext = entry
+        .path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    ext.and_then(|ext| ICONS.iter().find(|(e, _)| *e == ext).map(|(_, icon)| *icon))
+        .unwrap_or('\u{f15b}')
+}
+
+fn styled_name(entry: &Entry, long: bool, args: &Args, width: Option<usize>) -> String {
+    let path = entry.path.as_path();
+    // -F marks the file type after the name
+    let mark = |mark: &'static str| if args.classify { mark } else { "" };
+    if entry.is_dot() {
+        return format!(
+            "{}{}",
+            paint(&args.colors, &["di"], entry.dots(), "", |s| s.blue()),
+            mark("/")
+        );
+    }
+
+    let name = if args.relative_paths {
+        let relative = path.strip_prefix(".").unwrap_or(path);
+        quote(
+            &relative.to_string_lossy(),
+            args.quoting_style,
+            args.hide_control_chars,
+        )
+    } else {
+        quote(&entry.name(), args.quoting_style, args.hide_control_chars)
+    };
+    // `suffix` is the width of what follows the name
+    let fit = |suffix: usize| match width {
+        Some(width) => fit_name(&name, width.saturating_sub(suffix)),
+        None => name.clone(),
+    };
+    let file_type = entry.md.file_type();
+    let mode = entry.md.mode();
+    let raw_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let paint = |keys: &[&str], text: &str, fallback: fn(&str) -> ColoredString| {
+        paint(&args.colors, keys, text, &raw_name, fallback)
+    };
 
     if file_type.is_symlink() {
+        // orphans, links whose target is missing, fall back to the link color
+        let keys: &[&str] = if entry.target_md.is_some() {
+            &["ln"]
+        } else {
+            &["or", "ln"]
+        };
         if long {
-            if let Ok(target) = fs::read_link(path) {
-                if target.exists() {
+            if let Some(target) = &entry.target {
+                let target = target.to_string_lossy();
+                let mut target_name = if args.no_quote {
+                    target.to_string()
+                } else {
+                    quote(&target, args.quoting_style, args.hide_control_chars)
+                };
+                let mut name = name.clone();
+                // a long target leaves at least half of the room to the name
+                if let Some(width) = width {
+                    let width = width.saturating_sub(4);
+                    let room = width.saturating_sub(target_name.width()).max(width / 2);
+                    name = fit_name(&name, room);
+                    target_name = fit_name(&target_name, width - name.width());
+                }
+                let name = paint(keys, &name, |s| s.cyan());
+                if entry.target_md.is_some() {
                     return format!(
                         "{}{}{}",
-                        name.cyan(),
+                        name,
                         " -> ".cyan(),
-                        &target.to_string_lossy().cyan()
+                        paint(keys, &target_name, |s| s.cyan())
                     );
                 } else {
                     return format!(
                         "{}{}{}",
-                        name.cyan(),
+                        name,
                         " -> ".red(),
-                        &target.to_string_lossy().red()
+                        paint(&["mi"], &target_name, |s| s.red())
                     );
                 }
             }
         } else {
-            return format!("{}@", name.cyan());
+            let mark = mark("@");
+            // broken links are red, as their target is in long listings
+            if entry.target_md.is_some() {
+                return format!("{}{mark}", paint(keys, &fit(mark.len()), |s| s.cyan()));
+            } else {
+                return format!("{}{mark}", paint(keys, &fit(mark.len()), |s| s.red()));
+            }
         }
     } else if file_type.is_dir() {
-        return format!("{}/", name.blue());
+        let mark = mark("/");
+        let other_writable = mode & 0o002 != 0;
+        let sticky = mode & 0o1000 != 0;
+        let keys: &[&str] = match (sticky, other_writable) {
+            (true, true) => &["tw", "di"],
+            (false, true) => &["ow", "di"],
+            (true, false) => &["st", "di"],
+            (false, false) => &["di"],
+        };
+        return format!("{}{mark}", paint(keys, &fit(mark.len()), |s| s.blue()));
     } else if file_type.is_fifo() {
-        return format!("{}|", name.yellow());
+        let mark = mark("|");
+        return format!("{}{mark}", paint(&["pi"], &fit(mark.len()), |s| s.yellow()));
     } else if file_type.is_socket() {
-        return format!("{}=", name.red());
+        let mark = mark("=");
+        return format!("{}{mark}", paint(&["so"], &fit(mark.len()), |s| s.red()));
+    } else if file_type.is_block_device() {
+        return paint(&["bd"], &fit(0), |s| s.yellow());
+    } else if file_type.is_char_device() {
+        return paint(&["cd"], &fit(0), |s| s.magenta());
     }
-    name
+
+    // as in ls, setuid, setgid and executable files are colored as such
+    // whatever their name
+    let setuid: &[&str] = if mode & 0o4000 != 0 { &["su"] } else { &[] };
+    let setgid: &[&str] = if mode & 0o2000 != 0 { &["sg"] } else { &[] };
+    if mode & 0o111 != 0 {
+        let mark = mark("*");
+        let keys = [setuid, setgid, &["ex", "*", "fi"]].concat();
+        return format!("{}{mark}", paint(&keys, &fit(mark.len()), |s| s.green()));
+    }
+    let keys = [setuid, setgid, &["*", "fi"]].concat();
+    paint(&keys, &fit(0), |s| s.normal())
+}
+
+/// `text` in the color `LS_COLORS` gives the first of `keys` that it sets,
+/// else in the `fallback` color.
+fn paint(
+    colors: &Colors,
+    keys: &[&str],
+    text: &str,
+    name: &str,
+    fallback: fn(&str) -> ColoredString,
+) -> String {
+    match colors.lookup(keys, name) {
+        Some(code) => colors::paint(code, text),
+        None => fallback(text).to_string(),
+    }
+}
+
+pub(crate) fn inode(md: &Metadata, hex: bool) -> ColoredString {
+    if hex {
+        format!("{:#x}", md.ino()).cyan()
+    } else {
+        md.ino().to_string().cyan()
+    }
+}
+
+/// Allocated size in 1K blocks, `md.blocks()` being in 512-byte units.
+pub(crate) fn blocks(md: &Metadata) -> ColoredString {
+    md.blocks().div_ceil(2).to_string().bright_cyan()
+}
+
+/// Allocated size of all the entries in 1K blocks, for the `total` line.
+/// Hard links to the same file are counted once, as du does.
+pub(crate) fn total_blocks(entries: &[Entry]) -> u64 {
+    let mut seen = HashSet::new();
+    entries
+        .iter()
+        .filter(|e| seen.insert((e.md.dev(), e.md.ino())))
+        .map(|e| e.md.blocks())
+        .sum::<u64>()
+        .div_ceil(2)
+}
+
+/// Dims a cell but keeps its colors. `colored` ends each span with a reset,
+/// after which dimming has to be turned on again.
+pub(crate) fn dim(cell: &str) -> String {
+    if cell.is_empty() || !colored::control::SHOULD_COLORIZE.should_colorize() {
+        return cell.to_string();
+    }
+    format!(
+        "\x1b[2m{}\x1b[0m",
+        cell.replace("\x1b[0m", "\x1b[0m\x1b[2m")
+    )
+}
+
+/// Sums up the sizes of the files below `path`. Symlinks below it aren't
+/// followed, so there are no cycles, and hard links are counted once. `path`
+/// itself may be a link, e.g. one dereferenced with -L.
+pub(crate) fn total_size(path: &Path) -> u64 {
+    total_sizes(path).remove(path).unwrap_or(0)
 }
 
-pub(crate) fn file_size(md: &Metadata, bytes: bool) -> ColoredString {
+/// Same as `total_size`, for `path` and every directory below it at once, so
+/// that a tree needs a single walk. As with du, a file with hard links in
+/// several directories counts in the first one walked.
+pub(crate) fn total_sizes(path: &Path) -> HashMap<PathBuf, u64> {
+    fn walk(
+        path: &Path,
+        md: Metadata,
+        seen: &mut HashSet<(u64, u64)>,
+        sizes: &mut HashMap<PathBuf, u64>,
+    ) -> u64 {
+        if !seen.insert((md.dev(), md.ino())) {
+            return 0;
+        }
+        if md.is_dir() {
+            let size = fs::read_dir(path)
+                .map(|entries| {
+                    entries
+                        .filter_map(|e| e.ok())
+                        .filter_map(|e| Some((e.path(), e.path().symlink_metadata().ok()?)))
+                        .map(|(path, md)| walk(&path, md, seen, sizes))
+                        .sum()
+                })
+                .unwrap_or(0);
+            sizes.insert(path.to_path_buf(), size);
+            size
+        } else if md.is_file() {
+            md.len()
+        } else {
+            0
+        }
+    }
+    let mut sizes = HashMap::new();
+    if let Ok(md) = path.metadata() {
+        walk(path, md, &mut HashSet::new(), &mut sizes);
+    }
+    sizes
+}
+
+pub(crate) fn file_size(
+    md: &Metadata,
+    format: SizeFormat,
+    scale: Option<ColorScaleMode>,
+) -> String {
+    let file_type = md.file_type();
+    if file_type.is_block_device() || file_type.is_char_device() {
+        let (major, minor) = device_numbers(md.rdev());
+        return format!(
+            "{}{} {}",
+            major.to_string().green(),
+            ",".white(),
+            minor.to_string().green()
+        );
+    }
     if !md.is_file() {
-        return "-".white();
+        return "-".white().to_string();
     }
+    format_size(md.len(), format, scale)
+}
+
+/// Major and minor numbers of a device, split the way glibc packs them.
+fn device_numbers(rdev: u64) -> (u64, u64) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & 0xffff_ff00);
+    (major, minor)
+}
 
-    let len = md.len();
-    if bytes {
-        len.to_string()
-    } else if len < 1024 {
-        format!("{len}")
-    } else if len < 1024 * 1024 {
-        format!("{:.1}k", len as f64 / 1024.0)
-    } else if len < 1024 * 1024 * 1024 {
-        format!("{:.1}M", len as f64 / 1024.0 / 1024.0)
+pub(crate) fn format_size(len: u64, format: SizeFormat, scale: Option<ColorScaleMode>) -> String {
+    let (base, units) = match format {
+        SizeFormat::Binary => (1024, ["k", "M", "G"]),
+        SizeFormat::Si => (1000, ["kB", "MB", "GB"]),
+        SizeFormat::Bytes => (0, ["", "", ""]),
+    };
+    let (number, unit) = if format == SizeFormat::Bytes || len < base {
+        (len.to_string(), "")
+    } else if len < base * base {
+        (format!("{:.1}", len as f64 / base as f64), units[0])
+    } else if len < base * base * base {
+        (format!("{:.1}", len as f64 / base.pow(2) as f64), units[1])
+    } else {
+        (format!("{:.1}", len as f64 / base.pow(3) as f64), units[2])
+    };
+    let (number_color, unit_color) = match scale.map(|mode| size_color(len, mode)) {
+        Some(color) => (color, color),
+        None => (Color::Green, Color::BrightGreen),
+    };
+    if unit.is_empty() {
+        number.color(number_color).to_string()
     } else {
-        format!("{:.1}G", len as f64 / 1024.0 / 1024.0 / 1024.0)
+        format!("{}{}", number.color(number_color), unit.color(unit_color))
+    }
+}
+
+/// Upper bounds (exclusive, in bytes) of the fixed size color buckets.
+pub(crate) const SIZE_BUCKETS: [(u64, Color, &str); 4] = [
+    (1024, Color::Green, "< 1k"),
+    (1024 * 1024, Color::BrightGreen, "< 1M"),
+    (1024 * 1024 * 1024, Color::Yellow, "< 1G"),
+    (u64::MAX, Color::Red, "larger"),
+];
+
+/// Upper bounds (exclusive, in seconds) of the fixed age color buckets.
+pub(crate) const AGE_BUCKETS: [(i64, Color, &str); 4] = [
+    (60 * 60, Color::BrightGreen, "< 1 hour"),
+    (60 * 60 * 24, Color::Green, "< 1 day"),
+    (60 * 60 * 24 * 7, Color::Yellow, "< 1 week"),
+    (i64::MAX, Color::BrightBlack, "older"),
+];
+
+/// Ends of the size gradient, from empty to 1T.
+const SIZE_GRADIENT: [(u8, u8, u8); 2] = [(0x5f, 0xd7, 0x5f), (0xff, 0x5f, 0x5f)];
+
+/// Ends of the age gradient, from now to 10 years ago.
+const AGE_GRADIENT: [(u8, u8, u8); 2] = [(0x87, 0xff, 0x87), (0x60, 0x60, 0x60)];
+
+pub(crate) fn size_color(len: u64, mode: ColorScaleMode) -> Color {
+    match mode {
+        ColorScaleMode::Fixed => bucket(&SIZE_BUCKETS, len),
+        ColorScaleMode::Gradient => {
+            // log scale, so that small files don't all look the same
+            let ratio = (len as f64 + 1.0).ln() / ((1u64 << 40) as f64).ln();
+            gradient(SIZE_GRADIENT, ratio)
+        }
+    }
+}
+
+pub(crate) fn age_color(age: chrono::TimeDelta, mode: ColorScaleMode) -> Color {
+    let secs = age.num_seconds().max(0);
+    match mode {
+        ColorScaleMode::Fixed => bucket(&AGE_BUCKETS, secs),
+        ColorScaleMode::Gradient => {
+            let ratio = (secs as f64 + 1.0).ln() / (10.0 * 365.0 * 24.0 * 60.0 * 60.0f64).ln();
+            gradient(AGE_GRADIENT, ratio)
+        }
+    }
+}
+
+/// Describes the colors of the size scale, e.g. `size: < 1k, < 1M, < 1G, larger`.
+pub(crate) fn size_legend(mode: ColorScaleMode) -> String {
+    match mode {
+        ColorScaleMode::Fixed => legend("size", &SIZE_BUCKETS),
+        ColorScaleMode::Gradient => gradient_legend("size", SIZE_GRADIENT, "empty", "1T"),
+    }
+}
+
+/// Describes the colors of the age scale, e.g. `age: < 1 hour, < 1 day, ..., older`.
+pub(crate) fn age_legend(mode: ColorScaleMode) -> String {
+    match mode {
+        ColorScaleMode::Fixed => legend("age", &AGE_BUCKETS),
+        ColorScaleMode::Gradient => gradient_legend("age", AGE_GRADIENT, "now", "10 years"),
+    }
+}
+
+fn legend<T>(name: &str, buckets: &[(T, Color, &str)]) -> String {
+    let keys: Vec<String> = buckets
+        .iter()
+        .map(|(_, color, label)| label.color(*color).to_string())
+        .collect();
+    format!("{name}: {}", keys.join(", "))
+}
+
+fn gradient_legend(name: &str, ends: [(u8, u8, u8); 2], from: &str, to: &str) -> String {
+    format!(
+        "{name}: {} .. {}",
+        from.color(gradient(ends, 0.0)),
+        to.color(gradient(ends, 1.0))
+    )
+}
+
+fn bucket<T: PartialOrd>(buckets: &[(T, Color, &str)], value: T) -> Color {
+    buckets
+        .iter()
+        .find(|(bound, _, _)| value < *bound)
+        .or(buckets.last())
+        .map(|(_, color, _)| *color)
+        .unwrap_or(Color::White)
+}
+
+fn gradient([from, to]: [(u8, u8, u8); 2], ratio: f64) -> Color {
+    let ratio = ratio.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * ratio).round() as u8;
+    Color::TrueColor {
+        r: mix(from.0, to.0),
+        g: mix(from.1, to.1),
+        b: mix(from.2, to.2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn execute_shows_special_bits() {
+        colored::control::set_override(false);
+        assert_eq!(execute(0o755, 0o100, 0o4000, 's').to_string(), "x");
+        assert_eq!(execute(0o644, 0o100, 0o4000, 's').to_string(), "-");
+        assert_eq!(execute(0o4755, 0o100, 0o4000, 's').to_string(), "s");
+        assert_eq!(execute(0o4644, 0o100, 0o4000, 's').to_string(), "S");
+        assert_eq!(execute(0o1777, 0o001, 0o1000, 't').to_string(), "t");
+        assert_eq!(execute(0o1776, 0o001, 0o1000, 't').to_string(), "T");
+    }
+
+    #[test]
+    fn format_mode_of_files_and_directories() {
+        colored::control::set_override(false);
+        let dir = env::temp_dir().join(format!("lsr-format-mode-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+
+        let mode = |path: &Path, bits: u32, has_xattr: bool| {
+            fs::set_permissions(path, fs::Permissions::from_mode(bits)).unwrap();
+            format_mode(&path.symlink_metadata().unwrap(), has_xattr)
+        };
+        assert_eq!(mode(&file, 0o644, false), "-rw-r--r--");
+        assert_eq!(mode(&file, 0o4754, false), "-rwsr-xr--");
+        assert_eq!(mode(&file, 0o2640, true), "-rw-r-S---@");
+        assert_eq!(mode(&dir, 0o1777, false), "drwxrwxrwt");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn device_numbers_split_like_glibc() {
+        assert_eq!(device_numbers(0x103), (1, 3));
+        // as glibc's makedev(0x12345, 0x6789a)
+        let rdev = (0x345 << 8) | (0x12000 << 32) | 0x9a | (0x67800 << 12);
+        assert_eq!(device_numbers(rdev), (0x12345, 0x6789a));
+    }
+
+    #[test]
+    fn format_size_in_each_format() {
+        colored::control::set_override(false);
+        let size = |len, format| format_size(len, format, None);
+        assert_eq!(size(1000, SizeFormat::Binary), "1000");
+        assert_eq!(size(1000, SizeFormat::Si), "1.0kB");
+        assert_eq!(size(1000, SizeFormat::Bytes), "1000");
+        assert_eq!(size(1_000_000, SizeFormat::Binary), "976.6k");
+        assert_eq!(size(1_000_000, SizeFormat::Si), "1.0MB");
+        assert_eq!(size(1_500_000_000, SizeFormat::Binary), "1.4G");
+        assert_eq!(size(1_500_000_000, SizeFormat::Si), "1.5GB");
+        assert_eq!(size(1_500_000_000, SizeFormat::Bytes), "1500000000");
+    }
+
+    #[test]
+    fn date_relative_past_future_and_now() {
+        colored::control::set_override(false);
+        let relative = |offset: chrono::Duration| {
+            let date_time = Utc::now().fixed_offset() + offset;
+            date_relative(date_time, None, RelativePrecision::Approx)
+        };
+        let past = relative(-chrono::Duration::days(3) - chrono::Duration::minutes(1));
+        assert_eq!(past, " 3 days ago   ");
+        let future = relative(chrono::Duration::minutes(5) + chrono::Duration::seconds(30));
+        assert_eq!(future, "in 5 minutes  ");
+        assert_eq!(
+            relative(-chrono::Duration::milliseconds(200)),
+            "just now      "
+        );
+    }
+
+    #[test]
+    fn calendar_months_at_month_ends() {
+        let date = |s: &str| DateTime::parse_from_rfc3339(s).unwrap();
+        let jan31 = date("2025-01-31T12:00:00+00:00");
+        assert_eq!(calendar_months(jan31, date("2025-02-28T12:00:00+00:00")), 0);
+        assert_eq!(calendar_months(jan31, date("2025-03-01T12:00:00+00:00")), 1);
+        assert_eq!(calendar_months(jan31, date("2025-03-31T11:59:59+00:00")), 1);
+        assert_eq!(calendar_months(jan31, date("2025-03-31T12:00:00+00:00")), 2);
+        assert_eq!(
+            calendar_months(jan31, date("2026-01-31T12:00:00+00:00")),
+            12
+        );
+    }
+
+    #[test]
+    fn quote_in_each_style() {
+        assert_eq!(quote("a\tb", QuotingStyle::Literal, false), "a\tb");
+        assert_eq!(quote("a\tb", QuotingStyle::Literal, true), "a?b");
+        assert_eq!(
+            quote("plain-name.txt", QuotingStyle::Shell, false),
+            "plain-name.txt"
+        );
+        assert_eq!(quote("it's", QuotingStyle::Shell, false), "'it'\\''s'");
+        assert_eq!(quote("", QuotingStyle::Shell, false), "''");
+        assert_eq!(
+            quote("a\tb", QuotingStyle::ShellEscape, false),
+            "'a'$'\\t''b'"
+        );
+        assert_eq!(
+            quote("say \"hi\"", QuotingStyle::C, false),
+            "\"say \\\"hi\\\"\""
+        );
+    }
+
+    #[test]
+    fn c_escape_controls() {
+        assert_eq!(c_escape('a'), "a");
+        assert_eq!(c_escape('\\'), "\\\\");
+        assert_eq!(c_escape('\n'), "\\n");
+        assert_eq!(c_escape('\u{1b}'), "\\033");
+        assert_eq!(c_escape('\u{7f}'), "\\177");
+        assert_eq!(c_escape('\u{85}'), "\\205");
+        assert_eq!(c_escape('é'), "é");
     }
-    .green()
 }
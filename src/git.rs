@@ -0,0 +1,233 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use colored::{Color, Colorize};
+
+use crate::fs::canonical_entry;
+
+/// Last commit that touched a path: short hash and author name.
+type Blame = (String, String);
+
+/// Staged and unstaged status of a path, as in `git status --short`.
+type Status = (char, char);
+
+thread_local! {
+    /// Repository root of each directory seen, `None` outside of a repository.
+    static ROOTS: RefCell<HashMap<PathBuf, Option<PathBuf>>> = RefCell::new(HashMap::new());
+
+    /// Blame of every path in a repository, keyed by repository root. Filled
+    /// with a single `git log` the first time a file of the repository is seen.
+    static BLAME: RefCell<HashMap<PathBuf, HashMap<PathBuf, Blame>>> = RefCell::new(HashMap::new());
+
+    /// Status of every changed or untracked path in a repository, keyed by
+    /// repository root. Filled with a single `git status` like `BLAME`.
+    static STATUS: RefCell<HashMap<PathBuf, HashMap<PathBuf, Status>>> = RefCell::new(HashMap::new());
+
+    /// Ignored paths of a repository, keyed by repository root. An ignored
+    /// directory stands for everything below it.
+    static IGNORED: RefCell<HashMap<PathBuf, HashSet<PathBuf>>> = RefCell::new(HashMap::new());
+}
+
+fn repo_root(dir: &Path) -> Option<PathBuf> {
+    ROOTS.with(|roots| {
+        roots
+            .borrow_mut()
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| find_repo_root(dir))
+            .clone()
+    })
+}
+
+fn find_repo_root(dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+    ))
+}
+
+/// Walks the history newest first, so the first commit seen for a path, or
+/// for any path below a directory, is the last one that touched it.
+fn blame_repo(root: &Path) -> HashMap<PathBuf, Blame> {
+    let mut blame = HashMap::new();
+    let Ok(output) = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["log", "--format=%x01%h%x09%an", "--name-only", "-z"])
+        .output()
+    else {
+        return blame;
+    };
+
+    // with -z, paths are left unquoted and end with a NUL, and a newline
+    // separates the first one from the commit
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut commit = (String::new(), String::new());
+    let mut first = false;
+    for record in stdout.split('\0') {
+        if let Some(header) = record.strip_prefix('\u{1}') {
+            let (hash, author) = header.split_once('\t').unwrap_or((header, ""));
+            commit = (hash.to_string(), author.to_string());
+            first = true;
+            continue;
+        }
+        let line = match record.strip_prefix('\n') {
+            Some(line) if first => line,
+            _ => record,
+        };
+        first = false;
+        if !line.is_empty() {
+            for path in root.join(line).ancestors() {
+                if path == root || blame.contains_key(path) {
+                    break;
+                }
+                blame.insert(path.to_path_buf(), commit.clone());
+            }
+        }
+    }
+    blame
+}
+
+/// The last commit and author of a path, `-` outside of a repository.
+pub(crate) fn git_blame(path: &Path) -> String {
+    let path = canonical_entry(path);
+    let Some(root) = path.parent().and_then(repo_root) else {
+        return "-".white().to_string();
+    };
+
+    BLAME.with(|blame| {
+        let mut blame = blame.borrow_mut();
+        let repo = blame
+            .entry(root.clone())
+            .or_insert_with(|| blame_repo(&root));
+        match repo.get(&path) {
+            Some((hash, author)) => format!("{} {}", hash.yellow(), author.bright_yellow()),
+            None => "-".white().to_string(),
+        }
+    })
+}
+
+/// Reads the status of a repository. A directory gets, for each of the two
+/// positions, the first change found below it.
+fn status_repo(root: &Path) -> HashMap<PathBuf, Status> {
+    let mut status: HashMap<PathBuf, Status> = HashMap::new();
+    let Ok(output) = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["status", "--porcelain", "-z", "--untracked-files=all"])
+        .output()
+    else {
+        return status;
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut records = stdout.split('\0');
+    while let Some(record) = records.next() {
+        let mut chars = record.chars();
+        let (Some(x), Some(y)) = (chars.next(), chars.next()) else {
+            continue;
+        };
+        // a rename or copy is followed by the path it came from
+        if x == 'R' || x == 'C' {
+            records.next();
+        }
+        let Some(line) = record.get(3..) else {
+            continue;
+        };
+        for path in root.join(line).ancestors() {
+            if path == root {
+                break;
+            }
+            let (staged, unstaged) = status.entry(path.to_path_buf()).or_insert((' ', ' '));
+            if *staged == ' ' {
+                *staged = x;
+            }
+            if *unstaged == ' ' {
+                *unstaged = y;
+            }
+        }
+    }
+    status
+}
+
+/// Staged and unstaged status of a path, `--` when unchanged and blank outside
+/// of a repository.
+pub(crate) fn git_status(path: &Path) -> String {
+    let path = canonical_entry(path);
+    let Some(root) = path.parent().and_then(repo_root) else {
+        return "  ".to_string();
+    };
+
+    STATUS.with(|status| {
+        let mut status = status.borrow_mut();
+        let repo = status
+            .entry(root.clone())
+            .or_insert_with(|| status_repo(&root));
+        let (staged, unstaged) = repo.get(&path).copied().unwrap_or(('-', '-'));
+        let color = |c: char, color: Color| match c {
+            ' ' | '-' => c.to_string().white(),
+            '?' => c.to_string().bright_red(),
+            _ => c.to_string().color(color),
+        };
+        format!(
+            "{}{}",
+            color(staged, Color::Green),
+            color(unstaged, Color::Red)
+        )
+    })
+}
+
+/// Lets git apply the `.gitignore` files, `.git/info/exclude` and the global
+/// excludes, listing whole directories rather than their contents.
+fn ignored_repo(root: &Path) -> HashSet<PathBuf> {
+    let Ok(output) = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args([
+            "ls-files",
+            "--others",
+            "--ignored",
+            "--exclude-standard",
+            "--directory",
+            "-z",
+        ])
+        .output()
+    else {
+        return HashSet::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|line| !line.is_empty())
+        .map(|line| root.join(line.trim_end_matches('/')))
+        .collect()
+}
+
+/// Whether git ignores a path, `false` outside of a repository.
+pub(crate) fn is_ignored(path: &Path) -> bool {
+    let path = canonical_entry(path);
+    let Some(root) = path.parent().and_then(repo_root) else {
+        return false;
+    };
+
+    IGNORED.with(|ignored| {
+        let mut ignored = ignored.borrow_mut();
+        let repo = ignored
+            .entry(root.clone())
+            .or_insert_with(|| ignored_repo(&root));
+        path.ancestors()
+            .take_while(|dir| *dir != root)
+            .any(|dir| repo.contains(dir))
+    })
+}
@@ -0,0 +1,125 @@
+/// Whether `name` matches the shell glob `pattern`: `*` matches any run of
+/// bytes, `?` a single one, and `[...]` one of a set, with ranges and `!` or
+/// `^` to negate. A backslash matches the next byte literally.
+pub(crate) fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // where to resume after the last `*`: the pattern past it, and the name
+    // byte it is to swallow next
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, n));
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some(b'[') => {
+                if let Some((matched, len)) = class(&pattern[p..], name[n]) {
+                    if matched {
+                        p += len;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == b'[' {
+                    // unclosed, so a plain '['
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(b'\\') if p + 1 < pattern.len() && pattern[p + 1] == name[n] => {
+                p += 2;
+                n += 1;
+                continue;
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {}
+            Some(&c) if c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match star {
+            Some((star_p, star_n)) => {
+                p = star_p;
+                n = star_n + 1;
+                star = Some((star_p, n));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the class at the start of `pattern`, giving whether it
+/// matched and the length of the class, or `None` when it isn't closed.
+fn class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let lo = *pattern.get(i)?;
+        if lo == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&hi| hi != b']') {
+            matched |= lo <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches(b"*.rs", b"main.rs"));
+        assert!(matches(b"*.rs", b".rs"));
+        assert!(!matches(b"*.rs", b"main.rs.bak"));
+        assert!(matches(b"a*b*c", b"aXbYbZc"));
+        assert!(matches(b"?at", b"cat"));
+        assert!(!matches(b"?at", b"at"));
+        assert!(matches(b"*", b""));
+        assert!(!matches(b"", b"a"));
+    }
+
+    #[test]
+    fn matches_classes_and_escapes() {
+        assert!(matches(b"[abc]x", b"bx"));
+        assert!(matches(b"[a-c]x", b"cx"));
+        assert!(!matches(b"[!a-c]x", b"cx"));
+        assert!(matches(b"[^a-c]x", b"dx"));
+        assert!(matches(b"[]]", b"]"));
+        assert!(matches(b"[a-]", b"-"));
+        // an unclosed class is a plain '['
+        assert!(matches(b"[ab", b"[ab"));
+        assert!(matches(b"\\*", b"*"));
+        assert!(!matches(b"\\*", b"x"));
+    }
+
+    #[test]
+    fn class_length_and_negation() {
+        assert_eq!(class(b"[abc]rest", b'b'), Some((true, 5)));
+        assert_eq!(class(b"[!abc]", b'b'), Some((false, 6)));
+        assert_eq!(class(b"[0-9]", b'5'), Some((true, 5)));
+        assert_eq!(class(b"[]a]", b']'), Some((true, 4)));
+        assert_eq!(class(b"[abc", b'a'), None);
+    }
+}
@@ -0,0 +1,77 @@
+/// `s` as a quoted JSON string, for `--json` and `--error-format=json`.
+pub(crate) fn string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// `value` as JSON, `null` when there is none.
+pub(crate) fn or_null<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads back a string written by `string`.
+    fn parse(json: &str) -> String {
+        let inner = json
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .unwrap();
+        let mut s = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                assert!(c != '"' && !c.is_control(), "unescaped {c:?} in {json}");
+                s.push(c);
+                continue;
+            }
+            match chars.next().unwrap() {
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    s.push(char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+                }
+                c => s.push(c),
+            }
+        }
+        s
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(string(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(string("a\nb"), r#""a\nb""#);
+        assert_eq!(string("\u{1}"), r#""\u0001""#);
+    }
+
+    #[test]
+    fn string_round_trips() {
+        for s in [
+            "plain",
+            "\"quoted\"",
+            "two\nlines\r\n",
+            "tab\tand\\slash",
+            "bell\u{7}",
+            "é 日本",
+        ] {
+            assert_eq!(parse(&string(s)), s);
+        }
+    }
+}
@@ -1,177 +1,1071 @@
 mod cli;
+mod colors;
 mod fs;
+mod git;
+mod glob;
+mod json;
+mod report;
 
-use crate::cli::Args;
+use crate::cli::{Args, ColorWhen, SortBy, TimeField};
 use crate::fs::*;
-use clap::Parser;
+use crate::git::{git_blame, git_status, is_ignored};
+use crate::report::{report, report_fatal, report_note, report_serious};
+use ansi_width::ansi_width;
+use chrono::{DateTime, Local};
+use clap::{CommandFactory, Parser};
 use colored::Colorize;
 use std::{
-    io::{self, Error},
+    cmp::{Ordering, Reverse},
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsStr,
+    io::{self, IsTerminal, Read},
     os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    panic,
     path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
 };
 use tabular::{Row, Table};
 use term_grid::{Direction, Filling, Grid, GridOptions};
 use terminal_size::{terminal_size, Width};
 
-fn format_output_oneline(paths: &[PathBuf]) -> io::Result<String> {
+fn format_output_oneline(entries: &[Entry], args: &Args) -> io::Result<String> {
     let mut output = String::new();
-    for p in paths {
-        output.push_str(&file_name(p, true));
+    for entry in entries {
+        output.push_str(&file_name(entry, true, args));
         output.push('\n');
     }
     Ok(output)
 }
 
-fn format_output_short(paths: &[PathBuf], across: bool) -> io::Result<String> {
-    let term_size = terminal_size();
-    if let Some((Width(w), _)) = term_size {
-        let cells = paths.iter().map(|p| file_name(p, false)).collect();
-        let grid = Grid::new(
-            cells,
-            GridOptions {
-                filling: Filling::Spaces(2),
-                direction: if across {
-                    Direction::LeftToRight
-                } else {
-                    Direction::TopToBottom
-                },
-                width: w as usize,
-            },
-        );
-        Ok(format!("{grid}"))
-    } else {
-        Err(Error::new(
-            io::ErrorKind::Other,
-            "Failed to get terminal width.",
-        ))
+/// Names ending with a NUL byte, without any decoration, for `--zero`. With
+/// -R, which has no headers then, they are paths.
+fn format_output_zero(entries: &[Entry], args: &Args) -> io::Result<String> {
+    let mut output = String::new();
+    for entry in entries {
+        if args.relative_paths {
+            let path = entry.path.strip_prefix(".").unwrap_or(&entry.path);
+            output.push_str(&path.to_string_lossy());
+        } else if args.recursive {
+            output.push_str(&entry.path.to_string_lossy());
+        } else {
+            output.push_str(&entry.name());
+        }
+        output.push('\0');
+    }
+    Ok(output)
+}
+
+/// Width to lay out the output for: `--width`, else `$COLUMNS` when it is a
+/// positive number, else the width of the terminal. `None` when there is no
+/// terminal to go by.
+fn output_width(args: &Args) -> Option<usize> {
+    match args.width {
+        Some(0) => return Some(usize::MAX),
+        Some(width) => return Some(width),
+        None => {}
+    }
+    let columns = env::var("COLUMNS").ok().and_then(|c| c.parse().ok());
+    match (columns, terminal_size()) {
+        (Some(columns), _) if columns > 0 => Some(columns),
+        (_, Some((Width(w), _))) => Some(w as usize),
+        _ => None,
+    }
+}
+
+fn format_output_short(entries: &[Entry], args: &Args) -> io::Result<String> {
+    // piped output is one entry per line, as with ls, with the same cells as
+    // the grid
+    let Some(width) = output_width(args) else {
+        return Ok(entries
+            .iter()
+            .map(|e| file_name(e, false, args) + "\n")
+            .collect());
+    };
+    let options = || GridOptions {
+        filling: Filling::Spaces(2),
+        direction: if args.across {
+            Direction::LeftToRight
+        } else {
+            Direction::TopToBottom
+        },
+        width,
+    };
+    let grid = |cells: Vec<String>| {
+        if args.grid_balanced && !args.across {
+            let grid = Grid::new(cells.clone(), options());
+            let (lines, columns) = (grid.row_count(), grid.column_widths().len());
+            if let Some(balanced) = balanced_grid(&cells, lines, columns, width) {
+                return balanced;
+            }
+        }
+        format!("{}", Grid::new(cells, options()))
+    };
+
+    // Names wider than the terminal would force the whole grid into a single
+    // column, so they are given a row of their own between grids of the rest.
+    let mut output = String::new();
+    let mut cells = vec![];
+    for cell in entries.iter().map(|e| file_name(e, false, args)) {
+        if ansi_width(&cell) > width {
+            output.push_str(&grid(cells));
+            output.push_str(&cell);
+            output.push('\n');
+            cells = vec![];
+        } else {
+            cells.push(cell);
+        }
+    }
+    output.push_str(&grid(cells));
+    Ok(output)
+}
+
+/// Names separated by commas, as many on a line as fit the terminal, for
+/// `--comma`.
+fn format_output_comma(entries: &[Entry], args: &Args) -> io::Result<String> {
+    let width = output_width(args).unwrap_or(80);
+    let mut output = String::new();
+    let mut line_width = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let mut cell = file_name(entry, false, args);
+        if i + 1 < entries.len() {
+            cell.push(',');
+        }
+        let cell_width = ansi_width(&cell);
+        if line_width > 0 && line_width + 1 + cell_width > width {
+            output.push('\n');
+            line_width = 0;
+        } else if line_width > 0 {
+            output.push(' ');
+            line_width += 1;
+        }
+        output.push_str(&cell);
+        line_width += cell_width;
+    }
+    if !entries.is_empty() {
+        output.push('\n');
     }
+    Ok(output)
 }
 
+/// Re-lays out a top-to-bottom grid whose last column is less than half full
+/// with one column fewer, so the columns end up about the same height.
+fn balanced_grid(cells: &[String], lines: usize, columns: usize, width: usize) -> Option<String> {
+    if columns < 2 || cells.len() - (columns - 1) * lines >= lines.div_ceil(2) {
+        return None;
+    }
+
+    let lines = cells.len().div_ceil(columns - 1);
+    let widths: Vec<usize> = cells
+        .chunks(lines)
+        .map(|column| column.iter().map(|c| ansi_width(c)).max().unwrap_or(0))
+        .collect();
+    if widths.iter().sum::<usize>() + (widths.len() - 1) * 2 > width {
+        return None;
+    }
+
+    let mut output = String::new();
+    for y in 0..lines {
+        let row: Vec<_> = cells.iter().skip(y).step_by(lines).collect();
+        for (x, cell) in row.iter().enumerate() {
+            output.push_str(cell);
+            if x < row.len() - 1 {
+                output.push_str(&" ".repeat(widths[x] - ansi_width(cell) + 2));
+            }
+        }
+        output.push('\n');
+    }
+    Some(output)
+}
+
+/// Number of long-format columns before the name.
+const COLUMNS: usize = 16;
+
+/// Long-format columns dropped first to last, by index, to fit `--max-width`: git
+/// blame, security context, octal permissions, inode, blocks, allocated size,
+/// the extra times of --all-times, links, group, then git status.
+const DROP_ORDER: [usize; 12] = [14, 7, 3, 0, 1, 8, 13, 12, 11, 4, 6, 15];
+
 #[rustfmt::skip]
-fn format_output_long(paths: &[PathBuf], args: &Args) -> io::Result<String> {
-    let fmt = "{:>} {:<} {:>} {:<} {:<} {:>} {:<} {:<}";
+fn format_output_long(entries: &[Entry], args: &Args) -> io::Result<String> {
+    // the blocks, octal, owner, context, allocated, extra time, blame and git
+    // status columns bring their own separator, so that they take no room
+    // when empty
+    let fmt = "{:>} {:>}{:<}{:<} {:>} {:<}{:<}{:<}{:>}{:>} {:<}{:<}{:<}{:<} {:<}{:<}{:<}";
+    let show_group = (args.group || args.shared_group) && !args.no_group;
     let mut table = Table::new(fmt);
+    let lsm = if args.context { active_lsm() } else { None };
 
-    for path in paths {
-        let md = metadata(path)?;
-        let xattrs = xattrs(path);
-        let mut xattrs = xattrs.iter().peekable();
-
-        table.add_row(
-            Row::new()
-                .with_ansi_cell(if args.inode { md.ino().to_string().cyan() } else { "".white() })
-                .with_ansi_cell(if args.no_permissions { "".to_string() } else { format_mode(&md, xattrs.peek().is_some()) })
-                .with_ansi_cell(if args.links { md.nlink().to_string() } else { "".to_string() })
-                .with_ansi_cell(user_name(md.uid()))
-                .with_ansi_cell(if args.group { group_name(md.gid()) } else { "".white() })
-                .with_ansi_cell(file_size(&md, args.bytes))
-                .with_ansi_cell(modified_date(&md, args.time_style))
-                .with_ansi_cell(file_name(path, true))
-        );
+    let mut rows = entries.iter().map(|entry| {
+        let md = &entry.md;
+        let cells = [
+            if args.inode || args.inode_hex { inode(md, args.inode_hex).to_string() } else { "".to_string() },
+            if args.blocks { format!("{} ", blocks(md)) } else { "".to_string() },
+            if args.no_permissions { "".to_string() } else { format_perms(md, !entry.xattrs.is_empty(), args.perms.unwrap_or_default()) },
+            if args.octal && !args.no_permissions { format!(" {}", format_octal(md)) } else { "".to_string() },
+            if args.links { md.nlink().to_string() } else { "".to_string() },
+            // a hidden group still leaves its gap after the user, as in ls
+            if args.no_user { "".to_string() } else { format!("{}{}", user_name(md.uid(), args.numeric), if show_group { " " } else { "  " }) },
+            if show_group { format!("{} ", group(md, args.shared_group, args.numeric)) } else { "".to_string() },
+            if args.context { format!("{} ", security_context(&entry.path, lsm)) } else { "".to_string() },
+            if args.allocated { format!("{} ", format_size(md.blocks() * 512, args.size_format(), args.size_scale())) } else { "".to_string() },
+            match entry.total_size {
+                Some(size) => format_size(size, args.size_format(), args.size_scale()),
+                None => file_size(md, args.size_format(), args.size_scale()),
+            },
+            if args.all_times { file_date(md, TimeField::Modified, args) } else { file_date(md, args.time_field(), args) },
+            if args.all_times { format!(" {}", file_date(md, TimeField::Accessed, args)) } else { "".to_string() },
+            if args.all_times { format!(" {}", file_date(md, TimeField::Changed, args)) } else { "".to_string() },
+            if args.all_times { format!(" {}", file_date(md, TimeField::Created, args)) } else { "".to_string() },
+            if args.git_blame { format!("{} ", git_blame(&entry.path)) } else { "".to_string() },
+            if args.git { format!("{} ", git_status(&entry.path)) } else { "".to_string() },
+        ];
+        cells.map(|cell| if args.dim_metadata { dim(&cell) } else { cell })
+    }).collect::<Vec<_>>();
+
+    // width of the metadata columns and the five separators in fmt
+    let used = |rows: &[[String; COLUMNS]]| -> usize {
+        (0..COLUMNS).map(|i| rows.iter().map(|cells| ansi_width(&cells[i])).max().unwrap_or(0)).sum::<usize>() + 5
+    };
+
+    if let Some(max_width) = args.max_width {
+        let names = entries.iter().map(|e| ansi_width(&file_name(e, true, args))).max().unwrap_or(0);
+        for column in DROP_ORDER {
+            if used(&rows) + names <= max_width {
+                break;
+            }
+            rows.iter_mut().for_each(|cells| cells[column].clear());
+        }
+    }
+
+    // labels for the columns that have anything in them
+    let date = if args.all_times { "Modified" } else { "Date" };
+    let labels = ["Inode", "Blocks", "Permissions", "Octal", "Links", "User", "Group", "Context", "Allocated", "Size", date, "Accessed", "Changed", "Created", "Commit", "Git"];
+    let header: Option<[String; COLUMNS]> = (args.header && !rows.is_empty()).then(|| {
+        std::array::from_fn(|i| {
+            if rows.iter().all(|cells| cells[i].is_empty()) { "".to_string() }
+            else if [3, 11, 12, 13].contains(&i) { format!(" {}", labels[i].underline()) }
+            else if [1, 5, 6, 7, 8, 14, 15].contains(&i) { format!("{} ", labels[i].underline()) }
+            else { labels[i].underline().to_string() }
+        })
+    });
+    if let Some(header) = &header {
+        rows.insert(0, header.clone());
+    }
+
+    // names get what is left after the metadata columns, but stay recognizable
+    // even if that means overflowing
+    let width = if args.truncate_names { output_width(args) } else { None };
+    let name_width = match (width, args.max_width) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }.map(|width| width.saturating_sub(used(&rows)).max(8));
+
+    let mut rows = rows.into_iter();
+    if header.is_some() {
+        let mut row = Row::new();
+        for cell in rows.next().unwrap_or_default() {
+            row.add_ansi_cell(cell);
+        }
+        table.add_row(row.with_ansi_cell("Name".underline()));
+    }
+
+    for (entry, cells) in entries.iter().zip(rows) {
+        let mut xattrs = entry.xattrs.iter().peekable();
+        let mut row = Row::new();
+        for cell in cells {
+            row.add_ansi_cell(cell);
+        }
+        table.add_row(row.with_ansi_cell(file_name_fitting(entry, true, args, name_width)));
         if args.extended {
             while let Some(attr) = xattrs.next() {
-                table.add_row(
-                    Row::new()
-                        .with_ansi_cell("")
-                        .with_ansi_cell("")
-                        .with_ansi_cell("")
-                        .with_ansi_cell("")
-                        .with_ansi_cell("")
-                        .with_ansi_cell("")
-                        .with_ansi_cell("")
-                        .with_ansi_cell(
-                            if xattrs.peek().is_none() { format!("└── {attr}") } else { format!("├── {attr}") }
-                        ),
-                );
+                let mut row = Row::new();
+                for _ in 0..COLUMNS {
+                    row.add_ansi_cell("");
+                }
+                table.add_row(row.with_ansi_cell(
+                    if xattrs.peek().is_none() { format!("└── {attr}") } else { format!("├── {attr}") }
+                ));
             }
         }
     }
     Ok(format!("{table}"))
 }
 
-fn files_in(path: &Path, args: &Args) -> io::Result<Vec<PathBuf>> {
-    let mut results = vec![];
+/// Lists a directory and, depth first, everything below it with box-drawing
+/// connectors. Symlinks are only followed with -L, and never back up the tree.
+fn format_output_tree(dir: &Entry, args: &Args, summary: &mut Summary) -> String {
+    let mut tree = Tree {
+        args,
+        // one walk for all the sizes rather than one per directory shown
+        sizes: if args.total_size {
+            total_sizes(&dir.path)
+        } else {
+            HashMap::new()
+        },
+        ancestors: vec![dir.path.canonicalize().unwrap_or_else(|_| dir.path.clone())],
+        summary,
+        output: String::new(),
+    };
+    let mut dir = dir.clone();
+    set_total_size(&mut dir, args, Some(&tree.sizes));
+    tree.output = format!("{}\n", tree_name(&dir, args));
+    tree.walk(&dir.path, "", 1);
+    tree.output
+}
+
+/// What a `--tree` listing keeps track of while walking down.
+struct Tree<'a> {
+    args: &'a Args,
+    /// With --total-size, the sizes of the directories below the top one.
+    sizes: HashMap<PathBuf, u64>,
+    /// Canonical paths of the directories being walked, to spot links back up.
+    ancestors: Vec<PathBuf>,
+    summary: &'a mut Summary,
+    output: String,
+}
+
+impl Tree<'_> {
+    fn walk(&mut self, dir: &Path, prefix: &str, depth: usize) {
+        let args = self.args;
+        // an unreadable directory is shown, just without its contents
+        let (entries, omitted) = match list_dir(dir, args, Some(&self.sizes)) {
+            Ok((entries, omitted, _)) => (entries, omitted),
+            Err(e) if depth == 1 => {
+                report_serious(dir, &e, args.error_format);
+                return;
+            }
+            Err(e) => {
+                report(dir, &e, args.error_format);
+                return;
+            }
+        };
+        self.summary.add(&entries);
+        for (i, entry) in entries.iter().enumerate() {
+            let last = i == entries.len() - 1 && omitted == 0;
+            self.output.push_str(prefix);
+            self.output.push_str(if last { "└── " } else { "├── " });
+            self.output.push_str(&tree_name(entry, args));
+            self.output.push('\n');
+            if entry.md.is_dir() && args.level.is_none_or(|level| depth < level) {
+                // with -L, a link back up the tree is shown but not followed
+                let canonical = entry
+                    .path
+                    .canonicalize()
+                    .unwrap_or_else(|_| entry.path.clone());
+                if self.ancestors.contains(&canonical) {
+                    continue;
+                }
+                self.ancestors.push(canonical);
+                let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+                self.walk(&entry.path, &prefix, depth + 1);
+                self.ancestors.pop();
+            }
+        }
+        if omitted > 0 {
+            self.output
+                .push_str(&format!("{prefix}└── ... and {omitted} more\n"));
+        }
+    }
+}
+
+/// Sets the size of everything below a directory for --total-size, from
+/// `sizes` when a tree walked it already, else with a walk of its own, e.g.
+/// through a symlink followed with -L.
+fn set_total_size(entry: &mut Entry, args: &Args, sizes: Option<&HashMap<PathBuf, u64>>) {
+    if args.total_size && entry.md.is_dir() {
+        let size = sizes.and_then(|sizes| sizes.get(&entry.path).copied());
+        entry.total_size = Some(size.unwrap_or_else(|| total_size(&entry.path)));
+    }
+}
+
+fn tree_name(entry: &Entry, args: &Args) -> String {
+    if args.total_size {
+        let size = format_size(entry.size(), args.size_format(), args.size_scale());
+        let padding = " ".repeat(6usize.saturating_sub(ansi_width(&size)));
+        format!("{padding}{size} {}", file_name(entry, false, args))
+    } else {
+        file_name(entry, false, args)
+    }
+}
+
+/// One JSON object per entry, with the timestamp picked with -m, -u, -U or
+/// --changed as RFC 3339.
+fn format_output_json(entries: &[Entry], args: &Args) -> String {
+    let objects: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let md = &entry.md;
+            let time = timestamp(md, args.time_field()).map(|t| DateTime::<Local>::from(t).to_rfc3339());
+            format!(
+                r#"  {{"name":{},"path":{},"type":"{}","size":{},"mode":"{:04o}","uid":{},"gid":{},"user":{},"group":{},"nlink":{},"inode":{},"time":{}}}"#,
+                json::string(&entry.name()),
+                json::string(&entry.path.to_string_lossy()),
+                type_name(md.file_type()),
+                entry.size(),
+                md.mode() & 0o7777,
+                md.uid(),
+                md.gid(),
+                json::string(&user_name(md.uid(), args.numeric)),
+                json::string(&group_name(md.gid(), args.numeric)),
+                md.nlink(),
+                md.ino(),
+                json::or_null(time.map(|t| json::string(&t))),
+            )
+        })
+        .collect();
+    if objects.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n{}\n]\n", objects.join(",\n"))
+    }
+}
+
+fn format_output(entries: &[Entry], args: &Args) -> io::Result<String> {
+    if args.zero {
+        format_output_zero(entries, args)
+    } else if args.comma {
+        format_output_comma(entries, args)
+    } else if args.oneline {
+        format_output_oneline(entries, args)
+    } else if args.long {
+        format_output_long(entries, args)
+    } else {
+        format_output_short(entries, args)
+    }
+}
+
+/// Sorts directory entries. Whatever the sort key, ties are broken by name so
+/// the order is total and doesn't depend on the order `read_dir` yields.
+fn sort_entries(entries: &mut [Entry], args: &Args) {
+    let by_name = |a: &Entry, b: &Entry| {
+        a.path
+            .file_name()
+            .cmp(&b.path.file_name())
+            .then_with(|| a.path.cmp(&b.path))
+    };
+    match args.sort.time_field() {
+        Some(field) => entries.sort_by(|a, b| {
+            let time = |e: &Entry| Reverse(timestamp(&e.md, field));
+            time(a).cmp(&time(b)).then_with(|| by_name(a, b))
+        }),
+        None if args.sort == SortBy::Size => {
+            entries.sort_by(|a, b| b.size().cmp(&a.size()).then_with(|| by_name(a, b)))
+        }
+        None if args.sort == SortBy::Extension => entries.sort_by(|a, b| {
+            a.path
+                .extension()
+                .cmp(&b.path.extension())
+                .then_with(|| by_name(a, b))
+        }),
+        None if args.sort == SortBy::Version => entries.sort_by(|a, b| {
+            let (x, y) = (a.path.file_name(), b.path.file_name());
+            version_cmp(x.unwrap_or_default(), y.unwrap_or_default()).then_with(|| by_name(a, b))
+        }),
+        None if args.sort == SortBy::None => {}
+        None => entries.sort_by(by_name),
+    }
+    if args.reverse {
+        entries.reverse();
+    }
+    // a stable sort, so -r still applies within directories and files
+    if args.group_directories_first {
+        entries.sort_by_key(|e| !e.is_dir());
+    }
+}
+
+/// Compares names chunk by chunk, runs of digits by their value, so that
+/// `img2.png` comes before `img10.png`.
+fn version_cmp(a: &OsStr, b: &OsStr) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    while !a.is_empty() && !b.is_empty() {
+        let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+        let (da, db) = (digits(a), digits(b));
+        let ordering = if da > 0 && db > 0 {
+            let trim = |s: &[u8]| {
+                let zeros = s.iter().take_while(|&&c| c == b'0').count();
+                s[zeros..].to_vec()
+            };
+            let (na, nb) = (trim(&a[..da]), trim(&b[..db]));
+            // longer means larger once leading zeros are gone, and `007` comes after `7`
+            na.len()
+                .cmp(&nb.len())
+                .then_with(|| na.cmp(&nb))
+                .then(da.cmp(&db))
+        } else {
+            a[0].cmp(&b[0])
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        let step = if da > 0 && db > 0 { (da, db) } else { (1, 1) };
+        a = &a[step.0..];
+        b = &b[step.1..];
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Directories with fewer entries are stat'ed on a single thread.
+const PARALLEL_STAT_MIN: usize = 1024;
+
+/// Stats `paths` on all cores, in order, as stat'ing one entry at a time is
+/// slow for directories with many thousands of them.
+fn stat_all(paths: &[PathBuf], args: &Args) -> Vec<io::Result<Entry>> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    if threads == 1 || paths.len() < PARALLEL_STAT_MIN {
+        return paths.iter().map(|p| Entry::new(p.clone(), args)).collect();
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(paths.len().div_ceil(threads))
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(|p| Entry::new(p.clone(), args))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+fn files_in(path: &Path, args: &Args) -> io::Result<Vec<Entry>> {
+    Ok(list_dir(path, args, None)?.0)
+}
+
+/// Sorts entries and cuts them down to `--limit`, or the N of `--top`.
+/// Returns how many were left out.
+fn sort_and_limit(entries: &mut Vec<Entry>, args: &Args) -> usize {
+    sort_entries(entries, args);
+    limit(entries, args)
+}
+
+/// Cuts sorted entries down to `--limit`, returning how many were left out.
+fn limit(entries: &mut Vec<Entry>, args: &Args) -> usize {
+    let omitted = args.limit.map_or(0, |n| entries.len().saturating_sub(n));
+    entries.truncate(entries.len() - omitted);
+    omitted
+}
+
+/// Reads the paths in a directory that aren't left out by name, hidden,
+/// ignored or gitignored, without stat'ing them.
+fn read_names(path: &Path, args: &Args) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
     for entry in std::fs::read_dir(path)? {
         let entry = entry?;
-        let path = entry.path();
         let is_hidden = entry.file_name().as_os_str().as_bytes()[0] == b'.';
+        if args.all == 0 && is_hidden {
+            continue;
+        }
+        let name = entry.file_name();
+        if args
+            .ignore_glob
+            .iter()
+            .any(|pattern| glob::matches(pattern.as_bytes(), name.as_bytes()))
+        {
+            continue;
+        }
+        if args.gitignore && is_ignored(&entry.path()) {
+            continue;
+        }
+        paths.push(entry.path());
+    }
+    Ok(paths)
+}
 
-        if args.only_dirs ^ args.only_files {
-            if args.only_dirs && path.is_file() {
+/// Reads a directory into the entries to list, the number of entries left
+/// out by `--limit` and, with -R, the subdirectories to descend into, whether
+/// they are listed or not. `sizes` are directory sizes already known.
+fn list_dir(
+    path: &Path,
+    args: &Args,
+    sizes: Option<&HashMap<PathBuf, u64>>,
+) -> io::Result<(Vec<Entry>, usize, Vec<PathBuf>)> {
+    let paths = read_names(path, args)?;
+
+    let mut results = vec![];
+    let mut subdirs = vec![];
+    for (entry_path, entry) in paths.iter().zip(stat_all(&paths, args)) {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                note_vanished();
                 continue;
             }
-            if args.only_files && path.is_dir() {
+            // an entry that can't be stat'ed is reported, the rest is still listed
+            Err(e) => {
+                report(entry_path, &e, args.error_format);
                 continue;
             }
+        };
+        let mut listed = true;
+        if args.only_dirs ^ args.only_files {
+            if args.only_dirs && entry.is_file() {
+                listed = false;
+            }
+            if args.only_files && entry.is_dir() {
+                listed = false;
+            }
         }
-        if args.all > 0 || !is_hidden {
-            results.push(path);
+        if args.newer_than.is_some() || args.older_than.is_some() {
+            let modified = entry.md.modified()?;
+            if args.newer_than.is_some_and(|t| modified <= t)
+                || args.older_than.is_some_and(|t| modified >= t)
+            {
+                listed = false;
+            }
+        }
+        // before sorting, which may go by size
+        if listed {
+            set_total_size(&mut entry, args, sizes);
+        }
+        // symlinks to directories aren't descended into, unless dereferenced
+        if args.recursive && entry.md.is_dir() {
+            subdirs.push(entry.clone());
+        }
+        if listed {
+            results.push(entry);
         }
     }
 
-    results.sort();
+    sort_entries(&mut results, args);
+    sort_entries(&mut subdirs, args);
 
-    if args.all >= 2 {
-        results.insert(0, PathBuf::from("."));
-        results.insert(1, PathBuf::from(".."));
+    // a tree has no place for them
+    if args.all >= 2 && !args.tree {
+        for (i, dots) in [".", ".."].into_iter().enumerate() {
+            let mut entry = Entry::new(path.join(dots), args)?;
+            entry.dot = true;
+            results.insert(i, entry);
+        }
     }
+    // `.` and `..` count toward the limit like any entry
+    let omitted = limit(&mut results, args);
 
-    Ok(results)
+    Ok((
+        results,
+        omitted,
+        subdirs.into_iter().map(|e| e.path).collect(),
+    ))
 }
 
-fn main() -> io::Result<()> {
-    let args = Args::parse();
+/// The line above a directory listing: its path, quoted like names but
+/// otherwise plain.
+fn header(path: &Path, args: &Args) -> String {
+    let path = quote(
+        &path.to_string_lossy(),
+        args.quoting_style,
+        args.hide_control_chars,
+    );
+    format!("{path}:")
+}
 
-    let mut paths = args
-        .paths
-        .iter()
-        .map(PathBuf::from)
-        .filter(|p| {
-            if p.exists() {
-                true
+/// Notes the entries left out by `--limit` after a listing.
+fn print_omitted(omitted: usize, args: &Args) {
+    if omitted > 0 {
+        // keep one-per-line output clean for pipelines
+        if args.oneline {
+            report_note(&format!("... and {omitted} more"), args.error_format);
+        } else {
+            println!("... and {omitted} more");
+        }
+    }
+}
+
+/// Explains the color scales that were used: sizes in long listings and in
+/// trees with --total-size, ages in long listings only.
+fn print_legend(args: &Args) {
+    // the formats format_output picks before the long one
+    let long = args.long && !args.zero && !args.comma && !args.oneline;
+    if long || args.tree && args.total_size {
+        if let Some(mode) = args.size_scale() {
+            println!("{}", size_legend(mode));
+        }
+    }
+    if long {
+        if let Some(mode) = args.age_scale() {
+            println!("{}", age_legend(mode));
+        }
+    }
+}
+
+/// Does the same reading and stat'ing as a normal run, but prints only counts
+/// and timings, to see where the time goes on slow directories.
+fn print_stats(files: usize, directories: &[Entry], args: &Args) {
+    let (mut entries, mut listed) = (files, 0);
+    let (mut read, mut stat) = (Duration::ZERO, Duration::ZERO);
+    // directories are gone through as with -R or --tree, in no particular order
+    let mut pending: Vec<_> = directories.iter().map(|d| (d.path.clone(), 1)).collect();
+    let mut visited = HashSet::new();
+    while let Some((path, depth)) = pending.pop() {
+        if !visited.insert(path.canonicalize().unwrap_or_else(|_| path.clone())) {
+            continue;
+        }
+        let start = Instant::now();
+        let paths = match read_names(&path, args) {
+            Ok(paths) => paths,
+            Err(e) if depth == 1 => {
+                report_serious(&path, &e, args.error_format);
+                continue;
+            }
+            Err(e) => {
+                report(&path, &e, args.error_format);
+                continue;
+            }
+        };
+        read += start.elapsed();
+        let start = Instant::now();
+        let stated: Vec<_> = stat_all(&paths, args).into_iter().flatten().collect();
+        stat += start.elapsed();
+
+        listed += 1;
+        entries += stated.len();
+        let descend = args.recursive || args.tree && args.level.is_none_or(|level| depth < level);
+        if descend {
+            let subdirs = stated.into_iter().filter(|e| e.md.is_dir());
+            pending.extend(subdirs.map(|e| (e.path, depth + 1)));
+        }
+    }
+
+    eprintln!(
+        "{}, {}, read {:.2?}, stat {:.2?}",
+        count(entries, "entry", "entries"),
+        count(listed, "directory", "directories"),
+        read,
+        stat
+    );
+}
+
+/// `n` followed by the singular or plural noun.
+fn count(n: usize, one: &str, many: &str) -> String {
+    format!("{n} {}", if n == 1 { one } else { many })
+}
+
+/// What was listed, for `--summary`.
+#[derive(Default)]
+struct Summary {
+    files: usize,
+    directories: usize,
+    size: u64,
+    /// Device and inode of the files counted in `size`, so that hard links to
+    /// the same file add up once.
+    seen: HashSet<(u64, u64)>,
+}
+
+impl Summary {
+    fn add(&mut self, entries: &[Entry]) {
+        for entry in entries.iter().filter(|e| !e.is_dot()) {
+            if entry.is_dir() {
+                self.directories += 1;
             } else {
-                eprintln!("{}: No such file or directory.", file_name(p, false));
-                false
+                self.files += 1;
+                if self.seen.insert((entry.md.dev(), entry.md.ino())) {
+                    self.size += entry.md.len();
+                }
             }
-        })
-        .collect::<Vec<PathBuf>>();
+        }
+    }
+
+    fn print(&self, args: &Args) {
+        let line = format!(
+            "{}, {}, total {}",
+            count(self.files, "file", "files"),
+            count(self.directories, "directory", "directories"),
+            format_size(self.size, args.size_format(), None)
+        );
+        // keep one-per-line output clean for pipelines
+        if args.oneline {
+            report_note(&line, args.error_format);
+        } else {
+            println!("\n{line}");
+        }
+    }
+}
+
+/// Sets colors on or off from --color. `auto` goes by the environment, first
+/// match wins:
+/// - `CLICOLOR_FORCE` set to anything but `0` or an empty string turns them on,
+/// - `NO_COLOR` set to anything but an empty string, or `CLICOLOR=0`, turns
+///   them off,
+/// - otherwise they are on when stdout is a terminal that can display them,
+///   unlike serial consoles and CI logs with `TERM=dumb`.
+///
+/// JSON and `--zero` output are never colored.
+fn init_color(args: &Args) {
+    let set = |var: &str| env::var_os(var).filter(|v| !v.is_empty());
+    let color = match args.color {
+        _ if args.json || args.zero => false,
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto if set("CLICOLOR_FORCE").is_some_and(|v| v != "0") => true,
+        ColorWhen::Auto if set("NO_COLOR").is_some() => false,
+        ColorWhen::Auto if set("CLICOLOR").is_some_and(|v| v == "0") => false,
+        ColorWhen::Auto => {
+            let dumb = env::var("TERM").is_ok_and(|term| term == "dumb");
+            io::stdout().is_terminal() && !dumb
+        }
+    };
+    colored::control::set_override(color);
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let error_format = args.error_format;
+    // like ls, the listing goes on past errors but still fails in the end
+    if let Err(e) = list(args) {
+        report_fatal(&e, error_format);
+    }
+    ExitCode::from(report::status())
+}
 
-    paths.sort_by(|a, b| {
-        if a.is_dir() && !b.is_dir() {
-            std::cmp::Ordering::Greater
-        } else if !a.is_dir() && b.is_dir() {
-            std::cmp::Ordering::Less
+fn list(mut args: Args) -> io::Result<()> {
+    // before resolving options, which may touch the file system
+    if let Some(shell) = args.generate_completions {
+        clap_complete::generate(shell, &mut Args::command(), "lsr", &mut io::stdout());
+        return Ok(());
+    }
+
+    args.resolve()?;
+    init_color(&args);
+
+    let mut paths = vec![];
+    for path in &args.paths {
+        if path == "-" {
+            // names are bytes, not necessarily UTF-8
+            let mut input = vec![];
+            io::stdin().lock().read_to_end(&mut input)?;
+            let separator = if args.zero { b'\0' } else { b'\n' };
+            for name in input.split(|&b| b == separator).filter(|n| !n.is_empty()) {
+                paths.push(PathBuf::from(OsStr::from_bytes(name)));
+            }
         } else {
-            a.cmp(b)
+            paths.push(PathBuf::from(path));
         }
+    }
+
+    let mut entries = vec![];
+    for path in paths {
+        let entry = Entry::new(path.clone(), &args);
+        match entry.map(|e| {
+            if args.dereference_command_line {
+                e.dereferenced()
+            } else {
+                e
+            }
+        }) {
+            Ok(mut entry) => {
+                if args.total_size && entry.md.is_dir() && (args.directory || !args.tree) {
+                    entry.total_size = Some(total_size(&entry.path));
+                }
+                entries.push(entry)
+            }
+            Err(e) => report_serious(&path, &e, args.error_format),
+        }
+    }
+
+    // the same entry given twice, e.g. `src` and `./src`, is listed once
+    let mut seen = HashSet::new();
+    entries.retain(|e| seen.insert(canonical_entry(&e.path)));
+
+    // with -d, directories are listed like files rather than opened; either
+    // way, arguments are sorted like directory contents
+    let (mut files, mut directories): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| {
+        // as in ls, -l shows a symlink to a directory given as an argument
+        // rather than its contents, unless it was dereferenced with -H or -L
+        let is_dir = if args.long { e.md.is_dir() } else { e.is_dir() };
+        args.directory || !is_dir
     });
+    let omitted = sort_and_limit(&mut files, &args);
+    sort_entries(&mut directories, &args);
+
+    if args.stat_only {
+        print_stats(files.len() + omitted, &directories, &args);
+        return Ok(());
+    }
 
-    let (files, directories): (Vec<_>, Vec<_>) = paths.iter().cloned().partition(|f| !f.is_dir());
+    // a single array, directory contents following the files given
+    if args.json {
+        let mut entries = files;
+        for dir in &directories {
+            match files_in(&dir.path, &args) {
+                Ok(files) => entries.extend(files),
+                Err(e) => report_serious(&dir.path, &e, args.error_format),
+            }
+        }
+        print!("{}", format_output_json(&entries, &args));
+        return Ok(());
+    }
 
-    // print files first
-    if args.oneline {
-        print!("{}", format_output_oneline(&files)?)
-    } else if args.long {
-        print!("{}", format_output_long(&files, &args)?);
-    } else {
-        print!("{}", format_output_short(&files, args.across)?);
+    if args.merge {
+        let (mut entries, mut omitted) = (files, omitted);
+        for dir in &directories {
+            match list_dir(&dir.path, &args, None) {
+                Ok((files, more, _)) => {
+                    entries.extend(files);
+                    omitted += more;
+                }
+                Err(e) => report_serious(&dir.path, &e, args.error_format),
+            }
+        }
+        let mut seen = HashSet::new();
+        entries.retain(|e| seen.insert(canonical_entry(&e.path)));
+        // each part is cut down already, so this keeps the N first overall
+        omitted += sort_and_limit(&mut entries, &args);
+        print!("{}", format_output(&entries, &args)?);
+        print_omitted(omitted, &args);
+        if args.summary {
+            let mut summary = Summary::default();
+            summary.add(&entries);
+            summary.print(&args);
+        }
+        return Ok(());
     }
 
+    // print files first
+    print!("{}", format_output(&files, &args)?);
+    print_omitted(omitted, &args);
+    let mut summary = Summary::default();
+    summary.add(&files);
+    // listings are separated by an empty line
+    let mut printed = !files.is_empty();
+
     // print directories
-    for path in &directories {
-        let paths = files_in(path, &args)?;
-        if directories.len() > 1 {
-            println!("\n{}:", file_name(path, false));
+    for dir in &directories {
+        if args.tree {
+            if printed {
+                println!();
+            }
+            print!("{}", format_output_tree(dir, &args, &mut summary));
+            printed = true;
+            continue;
         }
-        if args.oneline {
-            print!("{}", format_output_oneline(&paths)?)
-        } else if args.long {
-            print!("{}", format_output_long(&paths, &args)?);
-        } else {
-            print!("{}", format_output_short(&paths, args.across)?);
+        // with -R, subdirectories are listed depth first after their parent
+        let mut pending = vec![dir.path.clone()];
+        let mut visited = HashSet::new();
+        while let Some(path) = pending.pop() {
+            // a symlink loop, only possible with -L
+            if !visited.insert(path.canonicalize().unwrap_or_else(|_| path.clone())) {
+                continue;
+            }
+            let (entries, omitted, subdirs) = match list_dir(&path, &args, None) {
+                Ok(listing) => listing,
+                // a directory given on the command line fails the run
+                Err(e) if path == dir.path => {
+                    report_serious(&path, &e, args.error_format);
+                    continue;
+                }
+                Err(e) => {
+                    report(&path, &e, args.error_format);
+                    continue;
+                }
+            };
+            // with --zero, headers would pass for names
+            if (path != dir.path || directories.len() > 1 || args.recursive) && !args.zero {
+                if printed {
+                    println!();
+                }
+                println!("{}", header(&path, &args));
+            }
+            printed = true;
+            summary.add(&entries);
+            if args.long && !args.oneline {
+                println!("total {}", total_blocks(&entries));
+            }
+            print!("{}", format_output(&entries, &args)?);
+            print_omitted(omitted, &args);
+            pending.extend(subdirs.into_iter().rev());
         }
     }
 
+    if args.summary {
+        summary.print(&args);
+    }
+
+    if args.legend {
+        print_legend(&args);
+    }
+
+    if vanished() > 0 {
+        let message = format!("{} entries vanished while listing.", vanished());
+        report_note(&message, args.error_format);
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_lines_up_wide_names() {
+        colored::control::set_override(false);
+        let dir = env::temp_dir().join(format!("lsr-wide-names-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let args = Args::parse_from(["lsr", "--width", "24"]);
+        let entries: Vec<_> = ["日本語.txt", "a.txt", "bb.txt", "c.txt"]
+            .iter()
+            .map(|name| {
+                std::fs::write(dir.join(name), "").unwrap();
+                Entry::new(dir.join(name), &args).unwrap()
+            })
+            .collect();
+
+        // 日本語.txt is 10 columns wide, so the second column starts at 12
+        let output = format_output_short(&entries, &args).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2, "{output}");
+        for (line, second) in lines.iter().zip(["bb.txt", "c.txt"]) {
+            let (first, _) = line.split_once(second).unwrap();
+            assert_eq!(ansi_width(first), 12, "{output}");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names = names.to_vec();
+        names.sort_by(|a, b| version_cmp(OsStr::new(a), OsStr::new(b)));
+        names.into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn version_cmp_orders_numbers_by_value() {
+        assert_eq!(
+            sorted(&["img10.png", "img2.png", "img1.png"]),
+            ["img1.png", "img2.png", "img10.png"]
+        );
+        assert_eq!(
+            sorted(&["v1.10", "v1.9", "v1.10.1"]),
+            ["v1.9", "v1.10", "v1.10.1"]
+        );
+        assert_eq!(sorted(&["b", "a10", "a9"]), ["a9", "a10", "b"]);
+    }
+
+    #[test]
+    fn version_cmp_leading_zeros_and_prefixes() {
+        assert_eq!(
+            version_cmp(OsStr::new("7"), OsStr::new("007")),
+            Ordering::Less
+        );
+        assert_eq!(
+            version_cmp(OsStr::new("007"), OsStr::new("8")),
+            Ordering::Less
+        );
+        assert_eq!(
+            version_cmp(OsStr::new("file"), OsStr::new("file1")),
+            Ordering::Less
+        );
+        assert_eq!(
+            version_cmp(OsStr::new("same2"), OsStr::new("same2")),
+            Ordering::Equal
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{cli::ErrorFormat, json};
+
+/// Exit status as in ls: 1 after minor problems such as an unreadable
+/// subdirectory, 2 when a path given can't be accessed.
+static STATUS: AtomicU8 = AtomicU8::new(0);
+
+/// Reports an error about `path` on stderr, in the format asked for with
+/// `--error-format`.
+pub(crate) fn report(path: &Path, err: &io::Error, format: ErrorFormat) {
+    STATUS.fetch_max(1, Ordering::Relaxed);
+    print(Some(path), err.raw_os_error(), &message(err), format);
+}
+
+/// Reports an error about a path given on the command line, which fails the
+/// run with status 2.
+pub(crate) fn report_serious(path: &Path, err: &io::Error, format: ErrorFormat) {
+    report(path, err, format);
+    STATUS.fetch_max(2, Ordering::Relaxed);
+}
+
+/// Reports an error that ends the run, such as an option naming a file that
+/// doesn't exist.
+pub(crate) fn report_fatal(err: &io::Error, format: ErrorFormat) {
+    STATUS.fetch_max(2, Ordering::Relaxed);
+    print(None, err.raw_os_error(), &message(err), format);
+}
+
+/// Reports something worth knowing that isn't an error, so it leaves the exit
+/// status alone. It is printed as is in the human format.
+pub(crate) fn report_note(message: &str, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Human => eprintln!("{message}"),
+        ErrorFormat::Json => print(None, None, message, format),
+    }
+}
+
+/// Human-readable errors are prefixed with the path, or with `lsr` when
+/// there is none; JSON ones have a null path then.
+fn print(path: Option<&Path>, errno: Option<i32>, message: &str, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Human => match path {
+            Some(path) => eprintln!("{}: {}.", path.display(), message),
+            None => eprintln!("lsr: {message}."),
+        },
+        ErrorFormat::Json => eprintln!(
+            r#"{{"path":{},"errno":{},"message":{}}}"#,
+            json::or_null(path.map(|p| json::string(&p.to_string_lossy()))),
+            json::or_null(errno),
+            json::string(message),
+        ),
+    }
+}
+
+/// The error message without the " (os error N)" suffix, as errno is
+/// reported on its own.
+fn message(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.rfind(" (os error ") {
+        Some(i) if err.raw_os_error().is_some() => message[..i].to_string(),
+        _ => message,
+    }
+}
+
+/// The exit status for the errors reported so far.
+pub(crate) fn status() -> u8 {
+    STATUS.load(Ordering::Relaxed)
+}